@@ -0,0 +1,110 @@
+use kmer_length::KmerLength;
+use nucleotide::Nucleotide;
+
+/// A set of sequence symbols that k-mers are built from.
+/// Symbols are packed into `BITS` bits each, and the encoding must sort the
+/// same way as the text representation so sorted k-mers are lexicographic.
+pub trait Alphabet: Copy + Send + Sync + 'static {
+    /// Stored in count databases to tell alphabets apart
+    const NAME: &'static str;
+    /// The number of bits each encoded symbol fits within
+    const BITS: u8;
+    /// The number of symbols, which are encoded as the codes below it
+    const SYMBOLS: u8;
+    /// Whether symbols are nucleotides, whose complement is `3 - code`
+    const COMPLEMENTED: bool;
+
+    /// Parses a text byte, returns `None` if it is not part of the alphabet
+    fn encode(c: u8) -> Option<u8>;
+
+    /// Turns an encoded symbol back into its (uppercase) text byte
+    fn decode(code: u8) -> u8;
+}
+
+#[derive(Clone, Copy)]
+pub struct Dna;
+
+impl Alphabet for Dna {
+    const NAME: &'static str = "dna";
+    const BITS: u8 = 2;
+    const SYMBOLS: u8 = 4;
+    const COMPLEMENTED: bool = true;
+
+    #[inline]
+    fn encode(c: u8) -> Option<u8> {
+        Nucleotide::from_text_byte(c).map(|n| n.into())
+    }
+
+    #[inline]
+    fn decode(code: u8) -> u8 {
+        Nucleotide::from_lower_bits(code).as_text_byte()
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Rna;
+
+impl Alphabet for Rna {
+    const NAME: &'static str = "rna";
+    const BITS: u8 = 2;
+    const SYMBOLS: u8 = 4;
+    const COMPLEMENTED: bool = true;
+
+    /// Also accepts T, so DNA input can be counted as RNA
+    #[inline]
+    fn encode(c: u8) -> Option<u8> {
+        match c {
+            b'u' | b'U' => Some(3),
+            _ => Dna::encode(c),
+        }
+    }
+
+    #[inline]
+    fn decode(code: u8) -> u8 {
+        match Dna::decode(code) {
+            b'T' => b'U',
+            c => c,
+        }
+    }
+}
+
+/// The IUPAC amino acid letters (every letter but J), in sorted order
+const PROTEIN_SYMBOLS: &[u8; 25] = b"ABCDEFGHIKLMNOPQRSTUVWXYZ";
+
+#[derive(Clone, Copy)]
+pub struct Protein;
+
+impl Alphabet for Protein {
+    const NAME: &'static str = "protein";
+    const BITS: u8 = 5;
+    const SYMBOLS: u8 = 25;
+    const COMPLEMENTED: bool = false;
+
+    #[inline]
+    fn encode(c: u8) -> Option<u8> {
+        PROTEIN_SYMBOLS.binary_search(&c.to_ascii_uppercase()).ok().map(|i| i as u8)
+    }
+
+    #[inline]
+    fn decode(code: u8) -> u8 {
+        PROTEIN_SYMBOLS[code as usize]
+    }
+}
+
+/// Runtime selection of an alphabet, used to pick which alphabet to run with
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum Kind {
+    Dna,
+    Rna,
+    Protein,
+}
+
+impl Kind {
+    pub fn max_kmer_length(self) -> u8 {
+        match self {
+            Kind::Dna => KmerLength::<Dna>::max_length(),
+            Kind::Rna => KmerLength::<Rna>::max_length(),
+            Kind::Protein => KmerLength::<Protein>::max_length(),
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::BufReader;
+use std::io::BufWriter;
+
+use errors::*;
+use alphabet::Alphabet;
+use kmer_length::KmerLength;
+use kmer_counts::KmerCounts;
+use sink::Sink;
+
+/// Identifies a count database, the trailing digit is the format version
+const MAGIC: &[u8; 8] = b"KMERCDB2";
+
+/// A sorted count table along with the inputs that were counted into it
+pub struct Database<A> {
+    pub kmer_len: KmerLength<A>,
+    pub inputs: Vec<String>,
+    pub counts: KmerCounts,
+}
+
+fn read_bytes<R: Read>(reader: &mut R, len: usize) -> Result<Vec<u8>> {
+    let mut buf = vec![0; len];
+    try!(reader.read_exact(buf.as_mut_slice()).chain_err(|| "Unexpected end of count database"));
+    Ok(buf)
+}
+
+/// Reads a little-endian integer of the given type
+macro_rules! read_le {
+    ($reader:expr, $t:ty) => {{
+        let mut buf = [0; ::std::mem::size_of::<$t>()];
+        try!($reader.read_exact(&mut buf).chain_err(|| "Unexpected end of count database"));
+        <$t>::from_le_bytes(buf)
+    }}
+}
+
+fn write_bytes<W: Write>(stream: &mut W, bytes: &[u8]) -> Result<()> {
+    stream.write_all(bytes).chain_err(|| "Failed to write count database")
+}
+
+pub fn read<R: Read, A: Alphabet>(mut reader: R) -> Result<Database<A>> {
+    let magic = try!(read_bytes(&mut reader, MAGIC.len()));
+    if magic.as_slice() != &MAGIC[..] {
+        bail!("Input is not a count database (bad magic number)");
+    }
+    let alphabet_len = read_le!(reader, u8) as usize;
+    let alphabet = try!(read_bytes(&mut reader, alphabet_len));
+    if alphabet.as_slice() != A::NAME.as_bytes() {
+        bail!("Count database is for the {} alphabet, not {}",
+              String::from_utf8_lossy(alphabet.as_slice()),
+              A::NAME);
+    }
+    let kmer_len = read_le!(reader, u8);
+    if !(1..=KmerLength::<A>::max_length()).contains(&kmer_len) {
+        bail!("Count database has invalid k-mer length {}", kmer_len);
+    }
+    let input_count = read_le!(reader, u32);
+    let mut inputs = Vec::new();
+    for _ in 0..input_count {
+        let len = read_le!(reader, u32) as usize;
+        let name = try!(read_bytes(&mut reader, len));
+        inputs.push(try!(String::from_utf8(name)
+            .chain_err(|| "Count database input name is not valid UTF-8")));
+    }
+    let count_len = read_le!(reader, u64);
+    let mut counts = KmerCounts::with_capacity(count_len as usize);
+    let mut last = None;
+    for _ in 0..count_len {
+        let kmer = read_le!(reader, u64);
+        let count = read_le!(reader, u16);
+        if let Some(last) = last {
+            if kmer <= last {
+                bail!("Count database is not sorted (k-mer {} after {})", kmer, last);
+            }
+        }
+        last = Some(kmer);
+        counts.push(kmer, count);
+    }
+    Ok(Database {
+        kmer_len: KmerLength::new(kmer_len),
+        inputs: inputs,
+        counts: counts,
+    })
+}
+
+/// Streams sorted and deduplicated counts into a database. The number of
+/// counts comes before them, so it is filled in once they have all been written.
+pub struct Writer<W: Write + Seek> {
+    stream: W,
+    count_len_offset: u64,
+    count_len: u64,
+}
+
+impl<W: Write + Seek> Writer<W> {
+    pub fn new<A: Alphabet>(mut stream: W,
+                            kmer_len: KmerLength<A>,
+                            inputs: &[String])
+                            -> Result<Writer<W>> {
+        try!(write_bytes(&mut stream, &MAGIC[..]));
+        try!(write_bytes(&mut stream, &[A::NAME.len() as u8]));
+        try!(write_bytes(&mut stream, A::NAME.as_bytes()));
+        try!(write_bytes(&mut stream, &[kmer_len.length()]));
+        try!(write_bytes(&mut stream, &(inputs.len() as u32).to_le_bytes()));
+        for input in inputs {
+            try!(write_bytes(&mut stream, &(input.len() as u32).to_le_bytes()));
+            try!(write_bytes(&mut stream, input.as_bytes()));
+        }
+        let count_len_offset = try!(stream.stream_position()
+            .chain_err(|| "Failed to write count database"));
+        try!(write_bytes(&mut stream, &0u64.to_le_bytes()));
+        Ok(Writer {
+            stream: stream,
+            count_len_offset: count_len_offset,
+            count_len: 0,
+        })
+    }
+}
+
+impl<W: Write + Seek> Sink for Writer<W> {
+    #[inline]
+    fn write(&mut self, kmer: u64, count: u16) -> Result<()> {
+        try!(write_bytes(&mut self.stream, &kmer.to_le_bytes()));
+        try!(write_bytes(&mut self.stream, &count.to_le_bytes()));
+        self.count_len += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        try!(self.stream
+            .seek(SeekFrom::Start(self.count_len_offset))
+            .chain_err(|| "Failed to write count database"));
+        try!(write_bytes(&mut self.stream, &self.count_len.to_le_bytes()));
+        try!(self.stream.seek(SeekFrom::End(0)).chain_err(|| "Failed to write count database"));
+        self.stream.flush().chain_err(|| "Failed to write count database")
+    }
+}
+
+pub fn open<A: Alphabet>(path: &str) -> Result<Database<A>> {
+    let file = try!(File::open(path).chain_err(|| "Failed to open count database"));
+    read(BufReader::new(file)).chain_err(|| format!("Failed to read count database {}", path))
+}
+
+/// Saves a database by writing it next to the destination and renaming it
+/// over the top when finished, so an interrupted run never leaves a
+/// truncated database.
+pub struct FileWriter {
+    writer: Writer<BufWriter<File>>,
+    tmp_path: String,
+    path: String,
+}
+
+pub fn create<A: Alphabet>(path: &str,
+                           kmer_len: KmerLength<A>,
+                           inputs: &[String])
+                           -> Result<FileWriter> {
+    let tmp_path = format!("{}.tmp", path);
+    let file = try!(File::create(&tmp_path)
+        .chain_err(|| "Failed to create temporary count database"));
+    Ok(FileWriter {
+        writer: try!(Writer::new(BufWriter::new(file), kmer_len, inputs)),
+        tmp_path: tmp_path,
+        path: path.to_string(),
+    })
+}
+
+impl Sink for FileWriter {
+    #[inline]
+    fn write(&mut self, kmer: u64, count: u16) -> Result<()> {
+        self.writer.write(kmer, count)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        try!(self.writer.finish());
+        try!(self.writer
+            .stream
+            .get_ref()
+            .sync_all()
+            .chain_err(|| "Failed to sync count database to disk"));
+        fs::rename(&self.tmp_path, &self.path).chain_err(|| "Failed to replace count database")
+    }
+}
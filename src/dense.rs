@@ -0,0 +1,120 @@
+use std::sync::Mutex;
+
+use jobsteal::Spawner;
+
+use errors::*;
+use alphabet::Alphabet;
+use kmer_length::KmerLength;
+use kmer_counts::KmerCounts;
+use sink::Sink;
+
+/// Above this many bits per k-mer, dense tables are only used when asked for.
+/// 24 bits is a 32 MiB table per thread.
+pub const AUTO_MAX_BITS: u32 = 24;
+/// Above this many bits per k-mer, dense tables are never used (512 MiB each)
+pub const MAX_BITS: u32 = 28;
+
+/// Entries summed per job when merging the tables
+const MERGE_CHUNK: usize = 1 << 20;
+
+/// A count for every possible k-mer, indexed by the encoded k-mer.
+/// Each job takes a table of its own, so counting needs no locking.
+pub struct Tables {
+    len: usize,
+    free: Mutex<Vec<Vec<u16>>>,
+}
+
+impl Tables {
+    pub fn new(bits: u32) -> Tables {
+        assert!(bits <= MAX_BITS);
+        Tables {
+            len: 1 << bits,
+            free: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Takes a table no other job is using, allocating one if there are none
+    pub fn take(&self) -> Vec<u16> {
+        let table = self.free.lock().unwrap().pop();
+        table.unwrap_or_else(|| vec![0; self.len])
+    }
+
+    /// Returns a table for later jobs to add to
+    pub fn put_back(&self, table: Vec<u16>) {
+        self.free.lock().unwrap().push(table);
+    }
+
+    /// Sums every table into one
+    pub fn merge(self, spawner: &Spawner) -> Vec<u16> {
+        let len = self.len;
+        let mut tables = self.free.into_inner().unwrap();
+        let mut total = tables.pop().unwrap_or_else(|| vec![0; len]);
+        if tables.is_empty() {
+            return total;
+        }
+        spawner.scope(|scope| {
+            let tables = &tables;
+            for (i, chunk) in total.chunks_mut(MERGE_CHUNK).enumerate() {
+                scope.submit(move || {
+                    let start = i * MERGE_CHUNK;
+                    for table in tables {
+                        let other = &table[start..start + chunk.len()];
+                        for (count, &other) in chunk.iter_mut().zip(other) {
+                            *count = count.saturating_add(other);
+                        }
+                    }
+                });
+            }
+        });
+        total
+    }
+}
+
+#[inline]
+pub fn increment(table: &mut [u16], kmer: u64) {
+    let count = &mut table[kmer as usize];
+    *count = count.saturating_add(1);
+}
+
+/// Adds already counted k-mers, such as an import or a database, to a table
+pub fn add_counts(table: &mut [u16], counts: &KmerCounts) {
+    for (kmer, count) in counts.iter() {
+        let total = &mut table[kmer as usize];
+        *total = total.saturating_add(count);
+    }
+}
+
+/// Whether every symbol of a k-mer is a valid code, which only fails for
+/// alphabets like proteins which don't fill all their bits
+fn valid_symbols<A: Alphabet>(mut kmer: u64, kmer_len: KmerLength<A>) -> bool {
+    let symbol_mask = (1 << A::BITS) - 1;
+    for _ in 0..kmer_len.length() {
+        if kmer & symbol_mask >= A::SYMBOLS as u64 {
+            return false;
+        }
+        kmer >>= A::BITS;
+    }
+    true
+}
+
+/// Writes the counts in k-mer order, which is lexicographic order.
+/// K-mers which were never seen are skipped unless `zero_counts` is set.
+/// Returns how many k-mers were written.
+pub fn write_all<A: Alphabet>(table: &[u16],
+                              kmer_len: KmerLength<A>,
+                              sink: &mut Sink,
+                              zero_counts: bool,
+                              only_presence: bool)
+                              -> Result<usize> {
+    let every_code_used = A::SYMBOLS as u64 == 1 << A::BITS;
+    let mut written = 0;
+    for (kmer, &count) in table.iter().enumerate() {
+        if count == 0 && !(zero_counts && (every_code_used || valid_symbols(kmer as u64, kmer_len))) {
+            continue;
+        }
+        let count = if only_presence { ::std::cmp::min(count, 1) } else { count };
+        try!(sink.write(kmer as u64, count));
+        written += 1;
+    }
+    Ok(written)
+}
@@ -0,0 +1,173 @@
+//! Splits reads by whether they share k-mers with a reference, like BBDuk,
+//! for screening reads for contaminants or pulling out targeted ones
+
+use std::fs::File;
+use std::io::Write;
+use std::io::BufWriter;
+
+use errors::*;
+use alphabet;
+use alphabet::Alphabet;
+use kmer_length::KmerLength;
+use database;
+use get_kmers;
+use matrix;
+use parsers::records;
+use parsers::records::Record;
+
+/// Where the reference k-mers come from
+pub enum Reference {
+    /// FASTA files, counted with the given k-mer length
+    Fasta(Vec<String>, u8),
+    /// A count database saved with `--update`, which sets the k-mer length
+    Database(String),
+}
+
+/// The options of the `filter` subcommand
+pub struct Options {
+    pub inputs: records::Inputs,
+    pub reference: Reference,
+    pub alphabet: alphabet::Kind,
+    /// Match nucleotide k-mers as they are, instead of their canonical form
+    pub stranded: bool,
+    /// Reads with at least this many k-mers in the reference match
+    pub min_matches: usize,
+    pub matched: Option<String>,
+    pub unmatched: Option<String>,
+    /// Where the second mates of pairs go, instead of after the first mates
+    pub matched2: Option<String>,
+    pub unmatched2: Option<String>,
+    pub threads: usize,
+}
+
+/// A sorted set of reference k-mers, and the reads' k-mers to look up in it
+pub struct Matcher<A> {
+    reference: Vec<u64>,
+    kmers: get_kmers::SliceKmers<A>,
+    canonical: bool,
+}
+
+impl<A: Alphabet> Matcher<A> {
+    /// `reference` must be sorted and deduplicated, and made of canonical
+    /// k-mers if `canonical` is set
+    pub fn new(reference: Vec<u64>, kmer_len: KmerLength<A>, canonical: bool) -> Matcher<A> {
+        Matcher {
+            reference: reference,
+            kmers: get_kmers::SliceKmers::new(kmer_len, get_kmers::Masking::All),
+            canonical: canonical,
+        }
+    }
+
+    /// The number of k-mers of a sequence which are in the reference,
+    /// counting repeated k-mers every time
+    pub fn matches(&mut self, seq: &[u8]) -> usize {
+        let Matcher { ref reference, ref mut kmers, canonical } = *self;
+        let mut matches = 0;
+        let mut matched = |kmer| if reference.binary_search(&kmer).is_ok() {
+            matches += 1;
+        };
+        if canonical {
+            kmers.for_each_canonical(seq, &mut matched);
+        } else {
+            kmers.for_each(seq, &mut matched);
+        }
+        matches
+    }
+}
+
+fn create(path: &Option<String>) -> Result<Option<BufWriter<File>>> {
+    match *path {
+        Some(ref path) => {
+            let file = try!(File::create(path)
+                .chain_err(|| format!("Failed to create output file {}", path)));
+            Ok(Some(BufWriter::new(file)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Writes a read, or one mate of a pair, to the outputs of its side of the
+/// filter. Second mates go to their own output if there is one.
+fn write(outputs: &mut [Option<BufWriter<File>>; 2], mate: usize, record: &Record) -> Result<()> {
+    let output = if mate == 1 && outputs[1].is_some() {
+        &mut outputs[1]
+    } else {
+        &mut outputs[0]
+    };
+    match *output {
+        Some(ref mut stream) => record.write(stream).chain_err(|| "Failed to write filtered reads"),
+        None => Ok(()),
+    }
+}
+
+pub fn run(opts: Options) -> Result<()> {
+    match opts.alphabet {
+        alphabet::Kind::Dna => run_alphabet::<alphabet::Dna>(opts),
+        alphabet::Kind::Rna => run_alphabet::<alphabet::Rna>(opts),
+        alphabet::Kind::Protein => run_alphabet::<alphabet::Protein>(opts),
+    }
+}
+
+fn run_alphabet<A: Alphabet>(opts: Options) -> Result<()> {
+    let Options { inputs,
+                  reference,
+                  stranded,
+                  min_matches,
+                  matched,
+                  unmatched,
+                  matched2,
+                  unmatched2,
+                  threads,
+                  .. } = opts;
+    let canonical = A::COMPLEMENTED && !stranded;
+    let (reference, kmer_len) = match reference {
+        Reference::Fasta(paths, kmer_len) => {
+            let kmer_len = KmerLength::<A>::new(kmer_len);
+            let mut kmers = Vec::new();
+            for path in paths.iter() {
+                kmers.extend(try!(matrix::distinct_kmers(path, kmer_len, canonical, threads, false)));
+            }
+            kmers.sort_unstable();
+            kmers.dedup();
+            (kmers, kmer_len)
+        }
+        Reference::Database(path) => {
+            let db = try!(database::open::<A>(&path));
+            let mut kmers = db.counts.kmers;
+            if canonical {
+                for kmer in kmers.iter_mut() {
+                    *kmer = db.kmer_len.canonical(*kmer);
+                }
+                kmers.sort_unstable();
+                kmers.dedup();
+            }
+            (kmers, db.kmer_len)
+        }
+    };
+    info!("Loaded {} reference k-mers", reference.len());
+
+    let mut matcher = Matcher::new(reference, kmer_len, canonical);
+    let mut matched = [try!(create(&matched)), try!(create(&matched2))];
+    let mut unmatched = [try!(create(&unmatched)), try!(create(&unmatched2))];
+    let (mut matched_count, mut total) = (0, 0);
+    // Both mates of a pair go to the same side, matching on their k-mers together
+    try!(records::for_each_fragment(&inputs, |fragment| {
+        total += 1;
+        let matches = fragment.iter().map(|record| matcher.matches(&record.seq)).sum::<usize>();
+        let output = if matches >= min_matches {
+            matched_count += 1;
+            &mut matched
+        } else {
+            &mut unmatched
+        };
+        for (mate, record) in fragment.iter().enumerate() {
+            try!(write(output, mate, record));
+        }
+        Ok(())
+    }));
+    for output in matched.iter_mut().chain(unmatched.iter_mut()).flat_map(|output| output.iter_mut()) {
+        try!(output.flush().chain_err(|| "Failed to write filtered reads"));
+    }
+    info!("Done! {} of {} reads or pairs matched the reference", matched_count, total);
+    Ok(())
+}
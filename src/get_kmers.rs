@@ -1,43 +1,272 @@
-use errors::*;
-use nucleotide::Nucleotide;
+use alphabet::Alphabet;
 use kmer_length::KmerLength;
+use simd;
 
-pub struct Kmers<T> {
-    input: T,
-    kmer_len: KmerLength,
-    buffer: u64,
+/// How soft-masked (lowercase) input is treated
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum Masking {
+    /// Case is ignored
+    All,
+    /// Lowercase bases break k-mers, so only unmasked k-mers are counted
+    SkipSoftMasked,
+    /// Uppercase bases break k-mers, so only masked k-mers are counted
+    OnlySoftMasked,
+}
+
+impl Masking {
+    #[inline]
+    fn breaks_at(self, c: u8) -> bool {
+        match self {
+            Masking::All => false,
+            Masking::SkipSoftMasked => c.is_ascii_lowercase(),
+            Masking::OnlySoftMasked => c.is_ascii_uppercase(),
+        }
+    }
+}
+
+/// Encoded symbols from here up aren't part of the alphabet
+const WHITESPACE: u8 = 0xfd;
+const INVALID: u8 = 0xfe;
+const MASKED: u8 = 0xff;
+
+/// Bytes of text encoded at a time
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Encodes whole slices of text through a lookup table
+pub struct Encoder {
+    table: [u8; 256],
 }
 
-impl<T: Iterator<Item = Result<Nucleotide>>> Kmers<T> {
-    pub fn new(mut input: T, kmer_len: KmerLength) -> Result<Kmers<T>> {
-        let mut buffer = 0u64;
-        for _ in 0..(kmer_len.length() - 1) {
-            match input.next() {
-                Some(Ok(n)) => {
-                    let n: u8 = n.into();
-                    buffer = n as u64 + (buffer << 2)
+impl Encoder {
+    pub fn new<A: Alphabet>(masking: Masking) -> Encoder {
+        let mut table = [0; 256];
+        for c in 0..256 {
+            let c = c as u8;
+            table[c as usize] = match c {
+                b' ' | b'\n' | b'\t' | b'\r' => WHITESPACE,
+                _ => {
+                    match A::encode(c) {
+                        None => INVALID,
+                        Some(_) if masking.breaks_at(c) => MASKED,
+                        Some(n) => n,
+                    }
                 }
-                Some(Err(e)) => return Err(e),
-                // The iterator will just return nothing:
-                None => break,
+            };
+        }
+        Encoder { table: table }
+    }
+
+    pub fn table(&self) -> &[u8; 256] {
+        &self.table
+    }
+
+    pub fn encode(&self, text: &[u8], codes: &mut Vec<u8>) {
+        codes.clear();
+        codes.extend(text.iter().map(|&c| self.table[c as usize]));
+    }
+}
+
+/// How many symbols a sequence had
+#[derive(Default, Clone, Copy)]
+pub struct Scanned {
+    /// Symbols of the alphabet, including masked ones
+    pub bases: u64,
+    /// Characters which aren't whitespace or part of the alphabet
+    pub invalid: u64,
+}
+
+impl Scanned {
+    pub fn add(&mut self, other: Scanned) {
+        self.bases += other.bases;
+        self.invalid += other.invalid;
+    }
+}
+
+/// A k-mer being built up one symbol at a time
+struct Roll {
+    buffer: u64,
+    /// The reverse complement of the buffer, when it is rolled as well
+    reverse: u64,
+    /// The number of symbols in the buffer since the last break
+    filled: u8,
+    scanned: Scanned,
+}
+
+impl Roll {
+    fn new() -> Roll {
+        Roll {
+            buffer: 0,
+            reverse: 0,
+            filled: 0,
+            scanned: Scanned::default(),
+        }
+    }
+
+    /// Adds a symbol, keeping the last `kmer_len` of them
+    #[inline]
+    fn shift<A: Alphabet>(&mut self, n: u8, kmer_len: KmerLength<A>) {
+        self.buffer = n as u64 + ((self.buffer << A::BITS) & kmer_len.bitmask());
+        self.scanned.bases += 1;
+        if self.filled < kmer_len.length() {
+            self.filled += 1;
+        }
+    }
+
+    #[inline]
+    fn push<A: Alphabet, F: FnMut(u64)>(&mut self, n: u8, kmer_len: KmerLength<A>, f: &mut F) {
+        self.shift(n, kmer_len);
+        if self.filled == kmer_len.length() {
+            f(self.buffer);
+        }
+    }
+
+    /// Like `push`, but rolls the reverse complement too and calls `f` with
+    /// the lesser of the two
+    #[inline]
+    fn push_canonical<A: Alphabet, F: FnMut(u64)>(&mut self,
+                                                   n: u8,
+                                                   kmer_len: KmerLength<A>,
+                                                   f: &mut F) {
+        self.shift(n, kmer_len);
+        // Complemented alphabets have two bits, and complementing flips both
+        let top = 2 * (kmer_len.length() as u32 - 1);
+        self.reverse = (self.reverse >> 2) | ((3 ^ n as u64) << top);
+        if self.filled == kmer_len.length() {
+            f(::std::cmp::min(self.buffer, self.reverse));
+        }
+    }
+
+    /// Handles a byte which isn't a symbol
+    #[inline]
+    fn skip<A: Alphabet>(&mut self, code: u8, c: u8) {
+        match code {
+            MASKED => {
+                self.filled = 0;
+                self.scanned.bases += 1;
+            }
+            INVALID => {
+                self.scanned.invalid += 1;
+                warn!("Encountered invalid {} character in input: {}",
+                      A::NAME,
+                      c as char)
             }
+            _ => {}
         }
-        Ok(Kmers {
-            input: input,
-            kmer_len: kmer_len,
-            buffer: buffer,
-        })
     }
 }
 
-impl<T: Iterator<Item = Result<Nucleotide>>> Iterator for Kmers<T> {
-    type Item = Result<u64>;
+/// Finds the k-mers of sequences given as slices of text, which may still
+/// contain line breaks. The text is encoded in bulk before k-mers are built,
+/// 32 bytes at a time with SIMD for nucleotides.
+pub struct SliceKmers<A> {
+    encoder: Encoder,
+    /// The letters encoded by `simd::pack`, if the alphabet is nucleotides
+    letters: Option<simd::Letters>,
+    level: simd::Level,
+    kmer_len: KmerLength<A>,
+    codes: Vec<u8>,
+}
+
+impl<A: Alphabet> SliceKmers<A> {
+    pub fn new(kmer_len: KmerLength<A>, masking: Masking) -> SliceKmers<A> {
+        SliceKmers::with_level(kmer_len, masking, simd::Level::detect())
+    }
+
+    /// Panics unless the CPU supports the level
+    pub fn with_level(kmer_len: KmerLength<A>, masking: Masking, level: simd::Level) -> SliceKmers<A> {
+        assert!(level.supported(), "{:?} isn't supported by this CPU", level);
+        let encoder = Encoder::new::<A>(masking);
+        let letters = simd::Letters::from_table(encoder.table());
+        SliceKmers {
+            encoder: encoder,
+            letters: letters,
+            level: level,
+            kmer_len: kmer_len,
+            codes: Vec::with_capacity(CHUNK_SIZE),
+        }
+    }
+
+    /// Calls `f` with every k-mer of one sequence
+    pub fn for_each<F: FnMut(u64)>(&mut self, text: &[u8], mut f: F) -> Scanned {
+        let kmer_len = self.kmer_len;
+        self.scan(text, |roll, n| roll.push(n, kmer_len, &mut f))
+    }
+
+    /// Calls `f` with the canonical form of every k-mer of one sequence, the
+    /// lesser of it and its reverse complement, which are rolled together
+    pub fn for_each_canonical<F: FnMut(u64)>(&mut self, text: &[u8], mut f: F) -> Scanned {
+        assert!(A::COMPLEMENTED);
+        let kmer_len = self.kmer_len;
+        self.scan(text, |roll, n| roll.push_canonical(n, kmer_len, &mut f))
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.input.next().map(|n| {
-            let n: u8 = try!(n).into();
-            self.buffer = n as u64 + ((self.buffer << 2) & self.kmer_len.bitmask());
-            Ok(self.buffer)
+    /// Calls `f` with the index of the length and every k-mer of each of
+    /// `lengths`, none of which may be longer than the length this was
+    /// created with, in one pass over the sequence. K-mers ending within the
+    /// first `skip` symbols are left out.
+    pub fn for_each_length<F>(&mut self,
+                              text: &[u8],
+                              lengths: &[KmerLength<A>],
+                              skip: usize,
+                              mut f: F)
+                              -> Scanned
+        where F: FnMut(usize, u64)
+    {
+        let kmer_len = self.kmer_len;
+        if lengths.len() == 1 && lengths[0].length() == kmer_len.length() &&
+           skip < kmer_len.length() as usize {
+            return self.for_each(text, |kmer| f(0, kmer));
+        }
+        self.scan(text, |roll, n| {
+            roll.shift(n, kmer_len);
+            if roll.scanned.bases as usize <= skip {
+                return;
+            }
+            for (i, length) in lengths.iter().enumerate() {
+                if roll.filled >= length.length() {
+                    f(i, roll.buffer & length.bitmask());
+                }
+            }
         })
     }
+
+    /// Calls `push` with every symbol of the text
+    #[inline]
+    fn scan<P: FnMut(&mut Roll, u8)>(&mut self, text: &[u8], mut push: P) -> Scanned {
+        let mut roll = Roll::new();
+        let mut rest = text;
+        if let Some(ref letters) = self.letters {
+            let chunks = text.chunks_exact(simd::CHUNK);
+            rest = chunks.remainder();
+            for chunk in chunks {
+                // The level was checked when this was created
+                let packed = unsafe { simd::pack(self.level, letters, chunk) };
+                if packed.valid == !0 {
+                    for i in 0..simd::CHUNK {
+                        push(&mut roll, packed.code(i));
+                    }
+                    continue;
+                }
+                // Anything but a letter is looked up
+                for (i, &c) in chunk.iter().enumerate() {
+                    if (packed.valid >> i) & 1 == 1 {
+                        push(&mut roll, packed.code(i));
+                    } else {
+                        roll.skip::<A>(self.encoder.table[c as usize], c);
+                    }
+                }
+            }
+        }
+        for chunk in rest.chunks(CHUNK_SIZE) {
+            self.encoder.encode(chunk, &mut self.codes);
+            for (&n, &c) in self.codes.iter().zip(chunk) {
+                if n >= WHITESPACE {
+                    roll.skip::<A>(n, c);
+                } else {
+                    push(&mut roll, n);
+                }
+            }
+        }
+        roll.scanned
+    }
 }
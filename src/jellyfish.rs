@@ -0,0 +1,82 @@
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+
+use errors::*;
+use alphabet::Alphabet;
+use kmer_length::KmerLength;
+use kmer_counts::KmerCounts;
+
+fn parse_kmer<A: Alphabet>(text: &str, kmer_len: KmerLength<A>) -> Result<u64> {
+    if text.len() != kmer_len.length() as usize {
+        bail!("Jellyfish k-mer {} does not have a length of {}",
+              text,
+              kmer_len.length());
+    }
+    let mut kmer = 0u64;
+    for &c in text.as_bytes() {
+        let n = match A::encode(c) {
+            Some(n) => n,
+            None => bail!("Jellyfish k-mer {} contains invalid character {}", text, c as char),
+        };
+        kmer = (kmer << A::BITS) | n as u64;
+    }
+    Ok(kmer)
+}
+
+fn parse_count(text: &str) -> Result<u16> {
+    let count = try!(text.parse::<u64>()
+        .chain_err(|| format!("Failed to parse Jellyfish count {}", text)));
+    if count > ::std::u16::MAX as u64 {
+        warn!("Jellyfish count {} does not fit and has been capped at {}",
+              count,
+              ::std::u16::MAX);
+        return Ok(::std::u16::MAX);
+    }
+    Ok(count as u16)
+}
+
+/// Reads the output of `jellyfish dump`, either in the default FASTA style
+/// (`>count` then the k-mer) or the column style from `-c` (optionally `-t`).
+pub fn read_dump<R, A>(reader: R, kmer_len: KmerLength<A>) -> Result<KmerCounts>
+    where R: BufRead,
+          A: Alphabet
+{
+    let mut counts = KmerCounts::new();
+    let mut pending_count = None;
+    for line in reader.lines() {
+        let line = try!(line.chain_err(|| "Failed to read Jellyfish dump"));
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(count) = line.strip_prefix('>') {
+            if pending_count.is_some() {
+                bail!("Jellyfish dump has a count header without a k-mer");
+            }
+            pending_count = Some(try!(parse_count(count)));
+        } else if let Some(count) = pending_count.take() {
+            counts.push(try!(parse_kmer(line, kmer_len)), count);
+        } else {
+            let mut fields = line.split_whitespace();
+            let kmer = fields.next().unwrap();
+            let count = match fields.next() {
+                Some(count) => count,
+                None => bail!("Jellyfish dump line {} has no count", line),
+            };
+            counts.push(try!(parse_kmer(kmer, kmer_len)), try!(parse_count(count)));
+        }
+    }
+    if pending_count.is_some() {
+        bail!("Jellyfish dump ends with a count header without a k-mer");
+    }
+    Ok(counts)
+}
+
+pub fn open_dump<A: Alphabet>(path: &str,
+                              kmer_len: KmerLength<A>)
+                              -> Result<KmerCounts> {
+    let file = try!(File::open(path).chain_err(|| "Failed to open Jellyfish dump"));
+    read_dump(BufReader::new(file), kmer_len)
+        .chain_err(|| format!("Failed to import Jellyfish dump {}", path))
+}
@@ -0,0 +1,84 @@
+use std::iter::FromIterator;
+
+use jobsteal::Spawner;
+
+use sort::sort;
+
+/// A list of k-mers and their counts, stored as separate arrays so each entry
+/// takes 10 bytes instead of the 16 a padded `(u64, u16)` would.
+#[derive(Default)]
+pub struct KmerCounts {
+    pub kmers: Vec<u64>,
+    pub counts: Vec<u16>,
+}
+
+impl KmerCounts {
+    pub fn new() -> KmerCounts {
+        KmerCounts::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> KmerCounts {
+        KmerCounts {
+            kmers: Vec::with_capacity(capacity),
+            counts: Vec::with_capacity(capacity),
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.kmers.len()
+    }
+
+    #[inline]
+    pub fn push(&mut self, kmer: u64, count: u16) {
+        self.kmers.push(kmer);
+        self.counts.push(count);
+    }
+
+    #[inline]
+    pub fn get(&self, index: usize) -> Option<(u64, u16)> {
+        self.kmers.get(index).map(|&kmer| (kmer, self.counts[index]))
+    }
+
+    /// Looks up the count of a k-mer in a sorted and deduplicated list,
+    /// 0 if it isn't there
+    pub fn count_of(&self, kmer: u64) -> u16 {
+        match self.kmers.binary_search(&kmer) {
+            Ok(index) => self.counts[index],
+            Err(_) => 0,
+        }
+    }
+
+    pub fn append(&mut self, other: &mut KmerCounts) {
+        self.kmers.append(&mut other.kmers);
+        self.counts.append(&mut other.counts);
+    }
+
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (u64, u16)> + 'a {
+        self.kmers.iter().cloned().zip(self.counts.iter().cloned())
+    }
+
+    /// Sorts by k-mer and merges duplicates, compacting the arrays
+    pub fn sort<F>(&mut self, merge_dups: F, spawner: Option<&Spawner>)
+        where F: Fn(&u64, &mut u16, u16) + Sync
+    {
+        sort(&mut self.kmers, &mut self.counts, merge_dups, spawner)
+    }
+}
+
+/// Merges the count of a duplicate k-mer into another, stopping at the
+/// largest count like the dense tables do
+pub fn add_counts(_: &u64, count: &mut u16, other: u16) {
+    *count = count.saturating_add(other);
+}
+
+impl FromIterator<(u64, u16)> for KmerCounts {
+    fn from_iter<I: IntoIterator<Item = (u64, u16)>>(iter: I) -> KmerCounts {
+        let iter = iter.into_iter();
+        let mut counts = KmerCounts::with_capacity(iter.size_hint().0);
+        for (kmer, count) in iter {
+            counts.push(kmer, count);
+        }
+        counts
+    }
+}
@@ -1,13 +1,19 @@
+use std::marker::PhantomData;
+
+use alphabet::Alphabet;
+
 #[derive(Clone, Copy)]
-pub struct KmerLength {
+pub struct KmerLength<A> {
     length: u8,
     bitmask: u64,
+    alphabet: PhantomData<A>,
 }
 
-impl KmerLength {
-    pub fn new(length: u8) -> KmerLength {
-        let bitmask = if length < 32 {
-            (1 << (2 * (length as u64))) - 1
+impl<A: Alphabet> KmerLength<A> {
+    pub fn new(length: u8) -> KmerLength<A> {
+        let bits = A::BITS as u64 * length as u64;
+        let bitmask = if bits < 64 {
+            (1 << bits) - 1
         } else {
             // We don't want it to overflow
             ::std::u64::MAX
@@ -15,9 +21,15 @@ impl KmerLength {
         KmerLength {
             length: length,
             bitmask: bitmask,
+            alphabet: PhantomData,
         }
     }
 
+    /// The longest k-mer that fits within a u64
+    pub fn max_length() -> u8 {
+        64 / A::BITS
+    }
+
     #[inline]
     pub fn length(&self) -> u8 {
         self.length
@@ -27,4 +39,23 @@ impl KmerLength {
     pub fn bitmask(&self) -> u64 {
         self.bitmask
     }
+
+    /// The reverse complement of a k-mer of a `COMPLEMENTED` alphabet
+    pub fn reverse_complement(&self, kmer: u64) -> u64 {
+        debug_assert!(A::COMPLEMENTED);
+        // Complementing is `3 - code`, which is the same as flipping both bits
+        let mut kmer = !kmer;
+        let mut reversed = 0;
+        for _ in 0..self.length {
+            reversed = (reversed << 2) | (kmer & 3);
+            kmer >>= 2;
+        }
+        reversed
+    }
+
+    /// The lesser of a k-mer and its reverse complement
+    #[inline]
+    pub fn canonical(&self, kmer: u64) -> u64 {
+        ::std::cmp::min(kmer, self.reverse_complement(kmer))
+    }
 }
@@ -4,7 +4,9 @@ use std::cmp::Ordering;
 
 use jobsteal::Spawner;
 
-use sort::sort;
+use errors::*;
+use kmer_counts::KmerCounts;
+use sink::Sink;
 
 #[derive(PartialEq, Eq, Clone)]
 pub enum JoinMethod {
@@ -14,38 +16,37 @@ pub enum JoinMethod {
 }
 
 pub struct Leaf {
-    pub counts: Vec<Option<(u64, u16)>>,
+    pub counts: KmerCounts,
+    /// Sorted leaves are also free of duplicates
     pub sorted: bool,
 }
 
 struct SortingQueueItem {
-    counts: Vec<Option<(u64, u16)>>,
+    counts: KmerCounts,
     index: usize,
 }
 
-// TODO: future optimization by collapsing trees
-// e.g. no use in join -> sort, concat -> sort is quicker
+// Join methods given with -j are used as they are. Only those from
+// `planner::plan` skip sorts below a hash join, where they are wasted.
 
 impl SortingQueueItem {
-    fn new(counts: Vec<Option<(u64, u16)>>) -> SortingQueueItem {
+    fn new(counts: KmerCounts) -> SortingQueueItem {
         SortingQueueItem {
             counts: counts,
             index: 0,
         }
     }
 
-    fn first(&self) -> Option<&(u64, u16)> {
-        self.counts[self.index..].iter().filter_map(|o| o.as_ref()).nth(0)
+    fn first(&self) -> Option<(u64, u16)> {
+        self.counts.get(self.index)
     }
 
     fn pop_first(&mut self) -> Option<(u64, u16)> {
-        while let Some(mut count) = self.counts.get_mut(self.index) {
+        let first = self.first();
+        if first.is_some() {
             self.index += 1;
-            if count.is_some() {
-                return count.take();
-            }
         }
-        None
+        first
     }
 }
 
@@ -91,6 +92,74 @@ impl PartialOrd for SortingQueueItem {
     }
 }
 
+/// Merges sorted leaves into a single sorted stream of k-mers and counts
+struct SortedMerge<'a, F: 'a> {
+    sorting_queue: BinaryHeap<SortingQueueItem>,
+    next_count: Option<(u64, u16)>,
+    last: Option<u64>,
+    merge_dups: &'a F,
+}
+
+impl<'a, F> SortedMerge<'a, F>
+    where F: Fn(&u64, &mut u16, u16) + Sync
+{
+    /// Sorts any unsorted leaves, then starts merging them
+    fn new<I>(leaves: I, spawner: &Spawner, merge_dups: &'a F) -> SortedMerge<'a, F>
+        where I: Iterator<Item = Leaf>
+    {
+        let mut sorting_queue = leaves.map(|n| {
+                let mut counts = n.counts;
+                if !n.sorted {
+                    counts.sort(merge_dups, Some(spawner));
+                }
+                SortingQueueItem::new(counts)
+            })
+            .collect::<BinaryHeap<_>>();
+        let next_count = sorting_queue.peek_mut().and_then(|mut item| item.pop_first());
+        SortedMerge {
+            sorting_queue: sorting_queue,
+            next_count: next_count,
+            last: None,
+            merge_dups: merge_dups,
+        }
+    }
+}
+
+impl<'a, F> Iterator for SortedMerge<'a, F>
+    where F: Fn(&u64, &mut u16, u16) + Sync
+{
+    type Item = (u64, u16);
+
+    fn next(&mut self) -> Option<(u64, u16)> {
+        let (kmer, mut count) = match self.next_count.take() {
+            Some(next_count) => next_count,
+            None => return None,
+        };
+        if let Some(last) = self.last {
+            if kmer < last {
+                panic!("Encountered kmer {} after last {}", kmer, last);
+            }
+        }
+        self.last = Some(kmer);
+        while let Some(mut item) = self.sorting_queue.peek_mut() {
+            if let Some(first) = item.pop_first() {
+                if kmer == first.0 {
+                    (self.merge_dups)(&kmer, &mut count, first.1);
+                } else {
+                    self.next_count = Some(first);
+                    break;
+                }
+            } else {
+                break;
+            }
+        }
+        Some((kmer, count))
+    }
+}
+
+/// Used when there are no more join methods for a depth
+static DEFAULT_JOIN_METHOD: JoinMethod = JoinMethod::Concat;
+
 pub enum Node {
     Branch(Vec<Node>),
     Leaf(Leaf),
@@ -108,14 +177,18 @@ impl Node {
             Node::Leaf(leaf) => return leaf,
             Node::Branch(children) => children,
         };
-        let default_sort_method = JoinMethod::Concat; // Extends lifetime
-        let join_methods_split = join_methods.split_first().unwrap_or((&default_sort_method, join_methods));
+        let join_methods_split = join_methods.split_first()
+            .unwrap_or((&DEFAULT_JOIN_METHOD, join_methods));
         let join_method = join_methods_split.0;
         let mut children = children.into_iter()
             .map(|n| n.consolidate(spawner, join_methods_split.1, merge_dups));
         match *join_method {
             JoinMethod::Concat => {
-                if let Some(first) = children.next() {
+                if children.len() == 1 {
+                    // Concatenating a single child changes nothing, so it
+                    // stays sorted if it was
+                    children.next().unwrap()
+                } else if let Some(first) = children.next() {
                     let children = children.map(|n| n.counts);
                     Leaf {
                         counts: children.fold(first.counts, |mut a, mut b| {
@@ -126,7 +199,7 @@ impl Node {
                     }
                 } else {
                     Leaf {
-                        counts: Vec::new(),
+                        counts: KmerCounts::new(),
                         sorted: true,
                     }
                 }
@@ -134,54 +207,57 @@ impl Node {
             JoinMethod::Join => {
                 let mut map = HashMap::new();
                 for child in children {
-                    for count in child.counts.into_iter().filter_map(|o| o) {
-                        *map.entry(count.0).or_insert(0) += count.1;
+                    for count in child.counts.iter() {
+                        let total = map.entry(count.0).or_insert(0u16);
+                        *total = total.saturating_add(count.1);
                     }
                 }
                 Leaf {
-                    counts: map.into_iter().map(Some).collect(),
+                    counts: map.into_iter().collect(),
                     sorted: false,
                 }
             }
             JoinMethod::Sort => {
-                let mut output = Vec::new();
-                let mut sorting_queue = children.map(|n| {
-                        let mut counts = n.counts;
-                        if !n.sorted {
-                            sort(counts.as_mut_slice(), merge_dups, Some(spawner));
-                        }
-                        SortingQueueItem::new(counts)
-                    })
-                    .collect::<BinaryHeap<_>>();
-                let mut next_count = sorting_queue.peek_mut().and_then(|mut item| item.pop_first());
-                let mut last = None;
-                while let Some((kmer, count)) = next_count.take() {
-                    if let Some(last) = last {
-                        if kmer < last {
-                            panic!("Encountered kmer {} after last {}", kmer, last);
-                        }
-                    }
-                    last = Some(kmer);
-                    let mut count = count;
-                    while let Some(mut item) = sorting_queue.peek_mut() {
-                        if let Some(first) = item.pop_first() {
-                            if kmer == first.0 {
-                                merge_dups(&kmer, &mut count, first.1);
-                            } else {
-                                next_count = Some(first);
-                                break;
-                            }
-                        } else {
-                            break;
-                        }
-                    }
-                    output.push(Some((kmer, count)));
-                }
                 Leaf {
-                    counts: output,
+                    counts: SortedMerge::new(children, spawner, merge_dups).collect(),
                     sorted: true,
                 }
             }
         }
     }
+
+    /// Consolidates the tree like `consolidate`, but writes the result into
+    /// a sink. If the top level is sorted, the merge is streamed straight
+    /// into the sink, so the merged counts are never held in memory.
+    /// Returns the number of k-mers written.
+    pub fn consolidate_into<F>(self,
+                               spawner: &Spawner,
+                               join_methods: &[JoinMethod],
+                               merge_dups: &F,
+                               sink: &mut Sink)
+                               -> Result<usize>
+        where F: Fn(&u64, &mut u16, u16) + Sync
+    {
+        let node = match self {
+            Node::Branch(children) => {
+                if let Some((&JoinMethod::Sort, child_methods)) = join_methods.split_first() {
+                    let children = children.into_iter()
+                        .map(|n| n.consolidate(spawner, child_methods, merge_dups));
+                    let mut written = 0;
+                    for (kmer, count) in SortedMerge::new(children, spawner, merge_dups) {
+                        try!(sink.write(kmer, count));
+                        written += 1;
+                    }
+                    return Ok(written);
+                }
+                Node::Branch(children)
+            }
+            leaf => leaf,
+        };
+        let leaf = node.consolidate(spawner, join_methods, merge_dups);
+        for (kmer, count) in leaf.counts.iter() {
+            try!(sink.write(kmer, count));
+        }
+        Ok(leaf.counts.len())
+    }
 }
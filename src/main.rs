@@ -6,6 +6,7 @@ extern crate clap;
 
 extern crate memmap;
 extern crate memchr;
+extern crate libc;
 
 extern crate jobsteal;
 
@@ -21,13 +22,28 @@ mod errors {
 }
 
 mod nucleotide;
+mod alphabet;
 mod kmer_length;
 mod get_kmers;
+mod simd;
 mod kmer_tree;
-mod error_string;
+mod kmer_counts;
 mod sort;
 mod output_counts;
+mod sink;
+mod stats;
 mod runner;
+mod planner;
+mod dense;
+mod pipeline;
+mod database;
+mod jellyfish;
+mod translate;
+mod sketch;
+mod matrix;
+mod profile;
+mod filter;
+mod unitigs;
 
 mod readers;
 mod parsers;
@@ -35,7 +51,227 @@ mod parsers;
 #[cfg(test)]
 mod tests;
 
-use kmer_length::KmerLength;
+/// Logs an error with its causes and exits
+fn exit_on_error(result: errors::Result<()>) {
+    if let Err(ref e) = result {
+        error!("{}", e);
+
+        for e in e.iter().skip(1) {
+            error!("Caused by: {}", e);
+        }
+
+        if let Some(backtrace) = e.backtrace() {
+            error!("Backtrace:\n{:?}", backtrace);
+        }
+
+        exit(2);
+    }
+}
+
+fn parse_threads(args: &clap::ArgMatches) -> usize {
+    args.value_of("threads")
+        .unwrap()
+        .parse::<usize>()
+        .unwrap_or_else(|e| {
+            error!("Failed to parse thread count as a positive integer:");
+            error!("{}", e);
+            exit(1);
+        })
+}
+
+/// The alphabet of a subcommand
+fn parse_alphabet(args: &clap::ArgMatches) -> alphabet::Kind {
+    match args.value_of("alphabet").unwrap() {
+        "dna" => alphabet::Kind::Dna,
+        "rna" => alphabet::Kind::Rna,
+        "protein" => alphabet::Kind::Protein,
+        alphabet @ _ => {
+            error!("Unknown alphabet {}", alphabet);
+            exit(1);
+        }
+    }
+}
+
+/// The alphabet and k-mer length of a subcommand
+fn parse_kmers(args: &clap::ArgMatches) -> (alphabet::Kind, u8) {
+    let alphabet = parse_alphabet(args);
+    let kmer_len = args.value_of("kmer_len")
+        .unwrap()
+        .parse::<u8>()
+        .unwrap_or_else(|e| {
+            error!("Failed to parse k-mer length as a positive integer:");
+            error!("{}", e);
+            exit(1);
+        });
+    if kmer_len < 1 || kmer_len > alphabet.max_kmer_length() {
+        error!("The kmer length {} is invalid as it must be from 1 to {} for this alphabet",
+               kmer_len,
+               alphabet.max_kmer_length());
+        exit(1);
+    }
+    (alphabet, kmer_len)
+}
+
+/// The reads of a subcommand which works read by read, paired or not
+fn parse_reads(args: &clap::ArgMatches) -> parsers::records::Inputs {
+    if let Some(mut paths) = args.values_of("paired") {
+        let first = paths.next().unwrap().to_string();
+        let second = paths.next().unwrap().to_string();
+        return parsers::records::Inputs::Paired(first, second);
+    }
+    let paths = args.values_of("inputs").unwrap().map(|s| s.to_string()).collect();
+    if args.is_present("interleaved") {
+        parsers::records::Inputs::Interleaved(paths)
+    } else {
+        parsers::records::Inputs::Single(paths)
+    }
+}
+
+fn sketch_command(args: &clap::ArgMatches) {
+    let (alphabet, kmer_len) = parse_kmers(args);
+    let mode = if let Some(scaled) = args.value_of("scaled") {
+        let scaled = scaled.parse::<u64>().unwrap_or_else(|e| {
+            error!("Failed to parse scaled as a positive integer:");
+            error!("{}", e);
+            exit(1);
+        });
+        if scaled < 1 {
+            error!("Scaled must be at least 1");
+            exit(1);
+        }
+        sketch::Mode::Scaled(scaled)
+    } else {
+        let size = args.value_of("size").unwrap_or("1000").parse::<usize>().unwrap_or_else(|e| {
+            error!("Failed to parse sketch size as a positive integer:");
+            error!("{}", e);
+            exit(1);
+        });
+        if size < 1 {
+            error!("Sketch size must be at least 1");
+            exit(1);
+        }
+        sketch::Mode::Bottom(size)
+    };
+    exit_on_error(sketch::run(sketch::Options {
+        inputs: args.values_of("inputs").unwrap().map(|s| s.to_string()).collect(),
+        output: args.value_of("output").unwrap().to_string(),
+        kmer_len: kmer_len,
+        alphabet: alphabet,
+        mode: mode,
+        stranded: args.is_present("stranded"),
+        threads: parse_threads(args),
+        mmap: args.is_present("mmap"),
+    }));
+}
+
+fn matrix_command(args: &clap::ArgMatches) {
+    let (alphabet, kmer_len) = parse_kmers(args);
+    let format = match args.value_of("format").unwrap() {
+        "tsv" => matrix::Format::Tsv,
+        "phylip" => matrix::Format::Phylip,
+        format @ _ => {
+            error!("Unknown matrix format {}", format);
+            exit(1);
+        }
+    };
+    exit_on_error(matrix::run(matrix::Options {
+        inputs: args.values_of("inputs").unwrap().map(|s| s.to_string()).collect(),
+        kmer_len: kmer_len,
+        alphabet: alphabet,
+        stranded: args.is_present("stranded"),
+        format: format,
+        threads: parse_threads(args),
+        mmap: args.is_present("mmap"),
+    }));
+}
+
+fn profile_command(args: &clap::ArgMatches) {
+    let threshold = args.value_of("threshold")
+        .unwrap()
+        .parse::<u16>()
+        .unwrap_or_else(|e| {
+            error!("Failed to parse threshold as a positive integer:");
+            error!("{}", e);
+            exit(1);
+        });
+    exit_on_error(profile::run(profile::Options {
+        inputs: parse_reads(args),
+        database: args.value_of("database").unwrap().to_string(),
+        alphabet: parse_alphabet(args),
+        threshold: threshold,
+    }));
+}
+
+fn filter_command(args: &clap::ArgMatches) {
+    let alphabet = parse_alphabet(args);
+    let reference = match args.value_of("database") {
+        Some(path) => filter::Reference::Database(path.to_string()),
+        None => {
+            let kmer_len = args.value_of("kmer_len")
+                .unwrap_or("31")
+                .parse::<u8>()
+                .unwrap_or_else(|e| {
+                    error!("Failed to parse k-mer length as a positive integer:");
+                    error!("{}", e);
+                    exit(1);
+                });
+            if kmer_len < 1 || kmer_len > alphabet.max_kmer_length() {
+                error!("The kmer length {} is invalid as it must be from 1 to {} for this alphabet",
+                       kmer_len,
+                       alphabet.max_kmer_length());
+                exit(1);
+            }
+            let paths = args.values_of("reference").unwrap().map(|s| s.to_string()).collect();
+            filter::Reference::Fasta(paths, kmer_len)
+        }
+    };
+    let min_matches = args.value_of("min_matches")
+        .unwrap()
+        .parse::<usize>()
+        .unwrap_or_else(|e| {
+            error!("Failed to parse minimum matches as a positive integer:");
+            error!("{}", e);
+            exit(1);
+        });
+    if min_matches < 1 {
+        error!("The minimum number of matching k-mers must be at least 1");
+        exit(1);
+    }
+    for &(second, first) in [("matched2", "matched"), ("unmatched2", "unmatched")].iter() {
+        if args.is_present(second) && !(args.is_present("paired") && args.is_present(first)) {
+            error!("--{} needs --paired input and --{}", second, first);
+            exit(1);
+        }
+    }
+    exit_on_error(filter::run(filter::Options {
+        inputs: parse_reads(args),
+        reference: reference,
+        alphabet: alphabet,
+        stranded: args.is_present("stranded"),
+        min_matches: min_matches,
+        matched: args.value_of("matched").map(|s| s.to_string()),
+        unmatched: args.value_of("unmatched").map(|s| s.to_string()),
+        matched2: args.value_of("matched2").map(|s| s.to_string()),
+        unmatched2: args.value_of("unmatched2").map(|s| s.to_string()),
+        threads: parse_threads(args),
+    }));
+}
+
+fn unitigs_command(args: &clap::ArgMatches) {
+    let min_count = args.value_of("min_count")
+        .unwrap()
+        .parse::<u16>()
+        .unwrap_or_else(|e| {
+            error!("Failed to parse minimum count as a positive integer:");
+            error!("{}", e);
+            exit(1);
+        });
+    exit_on_error(unitigs::run(unitigs::Options {
+        database: args.value_of("database").unwrap().to_string(),
+        min_count: min_count,
+        gfa: args.value_of("gfa").map(|s| s.to_string()),
+    }));
+}
 
 fn main() {
     env_logger::init().unwrap();
@@ -44,8 +280,264 @@ fn main() {
         .version("1.0")
         .author("Lee Bousfield <ljbousfield@gmail.com>")
         .about("Counts k-mers")
+        .setting(clap::AppSettings::SubcommandsNegateReqs)
+        .subcommand(clap::SubCommand::with_name("sketch")
+            .about("Saves a MinHash sketch of the k-mers of each input")
+            .arg(clap::Arg::with_name("inputs")
+                 .required(true)
+                 .multiple(true)
+                 .value_name("INPUTS...")
+                 .help("The input FASTA files, each is sketched separately"))
+            .arg(clap::Arg::with_name("output")
+                 .short("o")
+                 .long("output")
+                 .required(true)
+                 .takes_value(true)
+                 .value_name("FILE")
+                 .help("The sketch file to write"))
+            .arg(clap::Arg::with_name("kmer_len")
+                 .short("k")
+                 .long("kmer-length")
+                 .default_value("21")
+                 .value_name("LENGTH")
+                 .help("The length of hashed k-mers"))
+            .arg(clap::Arg::with_name("alphabet")
+                 .short("a")
+                 .long("alphabet")
+                 .default_value("dna")
+                 .possible_values(&["dna", "protein", "rna"])
+                 .help("The alphabet of the input sequences"))
+            .arg(clap::Arg::with_name("size")
+                 .short("s")
+                 .long("size")
+                 .takes_value(true)
+                 .value_name("N")
+                 .help("Keep the N smallest hashes of each input [default: 1000]"))
+            .arg(clap::Arg::with_name("scaled")
+                 .long("scaled")
+                 .takes_value(true)
+                 .value_name("N")
+                 .conflicts_with("size")
+                 .help("Instead of a fixed number, keep the hashes in the lowest 1/N of \
+                      the hash space (FracMinHash), so containment can be estimated \
+                      between inputs of very different sizes"))
+            .arg(clap::Arg::with_name("stranded")
+                 .long("stranded")
+                 .help("Hash nucleotide k-mers as they are, instead of the lesser of \
+                      each k-mer and its reverse complement"))
+            .arg(clap::Arg::with_name("threads")
+                 .short("t")
+                 .long("threads")
+                 .default_value("0")
+                 .help("The number of threads used, 0 will auto-optimize"))
+            .arg(clap::Arg::with_name("mmap")
+                 .long("mmap")
+                 .help("Use memory maps instead of traditional file I/O")))
+        .subcommand(clap::SubCommand::with_name("compare")
+            .about("Estimates the Jaccard index, containment and Mash distance between \
+                    every pair of saved sketches")
+            .arg(clap::Arg::with_name("sketches")
+                 .required(true)
+                 .multiple(true)
+                 .value_name("SKETCHES...")
+                 .help("The sketch files to compare")))
+        .subcommand(clap::SubCommand::with_name("matrix")
+            .about("Counts the distinct k-mers of each input separately and compares \
+                    every pair of inputs exactly")
+            .arg(clap::Arg::with_name("inputs")
+                 .required(true)
+                 .multiple(true)
+                 .value_name("INPUTS...")
+                 .help("The input FASTA files"))
+            .arg(clap::Arg::with_name("kmer_len")
+                 .short("k")
+                 .long("kmer-length")
+                 .default_value("21")
+                 .value_name("LENGTH")
+                 .help("The length of compared k-mers"))
+            .arg(clap::Arg::with_name("alphabet")
+                 .short("a")
+                 .long("alphabet")
+                 .default_value("dna")
+                 .possible_values(&["dna", "protein", "rna"])
+                 .help("The alphabet of the input sequences"))
+            .arg(clap::Arg::with_name("format")
+                 .short("f")
+                 .long("format")
+                 .default_value("tsv")
+                 .possible_values(&["tsv", "phylip"])
+                 .help("tsv writes a line per pair with the shared distinct k-mers, \
+                      the Jaccard index and the containment in both directions. \
+                      phylip writes a square matrix of the Mash distances implied by \
+                      the Jaccard indices, for tree building."))
+            .arg(clap::Arg::with_name("stranded")
+                 .long("stranded")
+                 .help("Compare nucleotide k-mers as they are, instead of the lesser of \
+                      each k-mer and its reverse complement"))
+            .arg(clap::Arg::with_name("threads")
+                 .short("t")
+                 .long("threads")
+                 .default_value("0")
+                 .help("The number of threads used, 0 will auto-optimize"))
+            .arg(clap::Arg::with_name("mmap")
+                 .long("mmap")
+                 .help("Use memory maps instead of traditional file I/O")))
+        .subcommand(clap::SubCommand::with_name("profile")
+            .about("Looks up the k-mers of each read in a count database, writing a \
+                    line per read with its ID, number of k-mers, minimum and median \
+                    count, fraction of counts below the threshold and every count in \
+                    order")
+            .arg(clap::Arg::with_name("inputs")
+                 .required_unless("paired")
+                 .multiple(true)
+                 .value_name("INPUTS...")
+                 .help("The input FASTA or FASTQ files"))
+            .arg(clap::Arg::with_name("paired")
+                 .long("paired")
+                 .takes_value(true)
+                 .number_of_values(2)
+                 .value_names(&["R1", "R2"])
+                 .conflicts_with_all(&["inputs", "interleaved"])
+                 .help("Read pairs from the files of first and second mates, which must \
+                      be in the same order"))
+            .arg(clap::Arg::with_name("interleaved")
+                 .long("interleaved")
+                 .help("The inputs hold pairs, with each read followed by its mate"))
+            .arg(clap::Arg::with_name("database")
+                 .short("d")
+                 .long("database")
+                 .required(true)
+                 .takes_value(true)
+                 .value_name("DB")
+                 .help("The count database saved with --update, which sets the k-mer \
+                      length"))
+            .arg(clap::Arg::with_name("alphabet")
+                 .short("a")
+                 .long("alphabet")
+                 .default_value("dna")
+                 .possible_values(&["dna", "protein", "rna"])
+                 .help("The alphabet of the input sequences and database"))
+            .arg(clap::Arg::with_name("threshold")
+                 .short("c")
+                 .long("threshold")
+                 .default_value("2")
+                 .help("Report the fraction of each read's k-mers with a count below \
+                      this")))
+        .subcommand(clap::SubCommand::with_name("filter")
+            .about("Splits reads by whether they share k-mers with a reference, \
+                    keeping their headers and qualities")
+            .arg(clap::Arg::with_name("inputs")
+                 .required_unless("paired")
+                 .multiple(true)
+                 .value_name("INPUTS...")
+                 .help("The input FASTA or FASTQ files"))
+            .arg(clap::Arg::with_name("paired")
+                 .long("paired")
+                 .takes_value(true)
+                 .number_of_values(2)
+                 .value_names(&["R1", "R2"])
+                 .conflicts_with_all(&["inputs", "interleaved"])
+                 .help("Read pairs from the files of first and second mates, which must \
+                      be in the same order"))
+            .arg(clap::Arg::with_name("interleaved")
+                 .long("interleaved")
+                 .help("The inputs hold pairs, with each read followed by its mate"))
+            .arg(clap::Arg::with_name("reference")
+                 .short("r")
+                 .long("ref")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1)
+                 .value_name("FASTA")
+                 .required_unless("database")
+                 .help("A FASTA file of reference sequences whose k-mers are matched"))
+            .arg(clap::Arg::with_name("database")
+                 .short("d")
+                 .long("database")
+                 .takes_value(true)
+                 .value_name("DB")
+                 .conflicts_with_all(&["reference", "kmer_len"])
+                 .help("Match the k-mers of a count database saved with --update \
+                      instead, which sets the k-mer length"))
+            .arg(clap::Arg::with_name("kmer_len")
+                 .short("k")
+                 .long("kmer-length")
+                 .takes_value(true)
+                 .value_name("LENGTH")
+                 .help("The length of matched k-mers [default: 31]"))
+            .arg(clap::Arg::with_name("alphabet")
+                 .short("a")
+                 .long("alphabet")
+                 .default_value("dna")
+                 .possible_values(&["dna", "protein", "rna"])
+                 .help("The alphabet of the sequences"))
+            .arg(clap::Arg::with_name("min_matches")
+                 .short("m")
+                 .long("min-matches")
+                 .default_value("1")
+                 .help("The number of a read's k-mers which must be in the reference \
+                      for it to match"))
+            .arg(clap::Arg::with_name("matched")
+                 .long("matched")
+                 .takes_value(true)
+                 .value_name("FILE")
+                 .required_unless("unmatched")
+                 .help("Write the matching reads to FILE. Pairs are written interleaved, \
+                      each read followed by its mate, unless --matched2 is given"))
+            .arg(clap::Arg::with_name("unmatched")
+                 .long("unmatched")
+                 .takes_value(true)
+                 .value_name("FILE")
+                 .help("Write the reads which don't match to FILE, interleaving pairs \
+                      unless --unmatched2 is given"))
+            .arg(clap::Arg::with_name("matched2")
+                 .long("matched2")
+                 .takes_value(true)
+                 .value_name("FILE")
+                 .help("Write the second mates of matching pairs to FILE, and only the \
+                      first mates to --matched, like the files given to --paired"))
+            .arg(clap::Arg::with_name("unmatched2")
+                 .long("unmatched2")
+                 .takes_value(true)
+                 .value_name("FILE")
+                 .help("Write the second mates of pairs which don't match to FILE, and \
+                      only the first mates to --unmatched"))
+            .arg(clap::Arg::with_name("stranded")
+                 .long("stranded")
+                 .help("Match nucleotide k-mers as they are, instead of the lesser of \
+                      each k-mer and its reverse complement"))
+            .arg(clap::Arg::with_name("threads")
+                 .short("t")
+                 .long("threads")
+                 .default_value("0")
+                 .help("The number of threads used to count the reference, 0 will \
+                      auto-optimize")))
+        .subcommand(clap::SubCommand::with_name("unitigs")
+            .about("Compacts the k-mers of a DNA count database into the unitigs of \
+                    their de Bruijn graph, written as FASTA with their mean k-mer \
+                    coverage")
+            .arg(clap::Arg::with_name("database")
+                 .short("d")
+                 .long("database")
+                 .required(true)
+                 .takes_value(true)
+                 .value_name("DB")
+                 .help("The count database saved with --update, which sets the k-mer \
+                      length"))
+            .arg(clap::Arg::with_name("min_count")
+                 .short("c")
+                 .long("min-count")
+                 .default_value("1")
+                 .help("Leave out k-mers seen fewer times, counting a k-mer and its \
+                      reverse complement together"))
+            .arg(clap::Arg::with_name("gfa")
+                 .long("gfa")
+                 .takes_value(true)
+                 .value_name("FILE")
+                 .help("Also write the unitigs and their k - 1 base overlaps to FILE \
+                      as GFA1")))
         .arg(clap::Arg::with_name("inputs")
-             .required_unless("stdin")
+             .required_unless_one(&["stdin", "import_jellyfish"])
              .multiple(true)
              .value_name("INPUTS...")
              .help("The input FASTA files"))
@@ -61,13 +553,54 @@ fn main() {
         .arg(clap::Arg::with_name("mmap")
              .long("mmap")
              .help("Use memory maps instead of traditional file I/O"))
+        .arg(clap::Arg::with_name("input_format")
+             .long("input-format")
+             .default_value("fasta")
+             .possible_values(&["fasta", "sam"])
+             .help("The format of the inputs. SAM inputs are counted from the read of \
+                  each alignment, reverse complemented back if it aligned to the \
+                  reverse strand."))
+        .arg(clap::Arg::with_name("primary_only")
+             .long("primary-only")
+             .help("Skip secondary and supplementary SAM alignments, so each read is \
+                  counted once"))
         .arg(clap::Arg::with_name("kmer_len")
              .short("k")
              .long("kmer-length")
              .required(true)
              .takes_value(true)
-             .value_name("LENGTH")
-             .help("The length of generated k-mers"))
+             .value_name("LENGTHS")
+             .help("The length of generated k-mers. Several comma separated lengths \
+                  are counted in one pass over the inputs, and need --output-prefix."))
+        .arg(clap::Arg::with_name("output_prefix")
+             .short("o")
+             .long("output-prefix")
+             .takes_value(true)
+             .value_name("PREFIX")
+             .help("Write the output for each k-mer length to PREFIX.k<LENGTH> instead \
+                  of stdout"))
+        .arg(clap::Arg::with_name("alphabet")
+             .short("a")
+             .long("alphabet")
+             .default_value("dna")
+             .possible_values(&["dna", "protein", "rna"])
+             .help("The alphabet of the input sequences"))
+        .arg(clap::Arg::with_name("translate")
+             .long("translate")
+             .takes_value(true)
+             .value_name("CODE")
+             .possible_values(&["standard", "bacterial", "mitochondrial"])
+             .help("Translate the DNA input in all six reading frames with the given \
+                  genetic code and count the amino acid k-mers, stop codons break \
+                  k-mers. Implies --alphabet protein."))
+        .arg(clap::Arg::with_name("skip_soft_masked")
+             .long("skip-soft-masked")
+             .conflicts_with_all(&["only_soft_masked", "translate"])
+             .help("Treat lowercase (soft-masked) bases as k-mer breaks"))
+        .arg(clap::Arg::with_name("only_soft_masked")
+             .long("only-soft-masked")
+             .conflicts_with("translate")
+             .help("Treat uppercase bases as k-mer breaks, only counting soft-masked k-mers"))
         .arg(clap::Arg::with_name("only_presence")
              .short("p")
              .long("only-presence")
@@ -83,43 +616,171 @@ fn main() {
              .multiple(true)
              .require_delimiter(true)
              .value_name("METHODS...")
-             .possible_values(&["concat", "join", "sort"])
+             .possible_values(&["auto", "concat", "join", "sort"])
              .help("The methods sorted by depth used to join kmer lists together, \
-                  defaults to concat. Comma separated. Note that concat does not add \
-                  duplicate counts, and join output ordering is random."))
+                  defaults to concat. Comma separated. The output is always sorted and \
+                  deduplicated after joining, unless --unordered is given. auto picks \
+                  the methods for every depth from the input (and logs them)."))
+        .arg(clap::Arg::with_name("unordered")
+             .long("unordered")
+             .conflicts_with_all(&["update", "top"])
+             .help("Output the k-mers as the join methods leave them, skipping the final \
+                  sort. Note that concat does not add duplicate counts, and join output \
+                  ordering is random. Dense counting is always sorted."))
+        .arg(clap::Arg::with_name("counting")
+             .long("counting")
+             .default_value("auto")
+             .possible_values(&["auto", "dense", "tree"])
+             .help("How k-mers are counted. dense keeps a count for every possible \
+                  k-mer, which is fastest for short k-mers (up to 14 DNA bases), and \
+                  always outputs sorted and merged counts. tree uses the join methods. \
+                  auto uses dense counting for up to 12 DNA bases."))
+        .arg(clap::Arg::with_name("flush_threshold")
+             .long("flush-threshold")
+             .default_value("1048576")
+             .value_name("KMERS")
+             .help("With tree counting, the k-mers of consecutive sections are \
+                  accumulated into one list until it holds this many, so inputs with \
+                  many short records don't make a leaf each. 0 makes a leaf for \
+                  every section."))
+        .arg(clap::Arg::with_name("zero_counts")
+             .long("zero-counts")
+             .conflicts_with("update")
+             .help("Also output k-mers with a count of zero, ignoring --min-count. \
+                  Requires dense counting."))
+        .arg(clap::Arg::with_name("format")
+             .short("f")
+             .long("format")
+             .default_value("tsv")
+             .possible_values(&["tsv", "jellyfish", "jellyfish-column"])
+             .help("The output format. jellyfish and jellyfish-column match the \
+                  output of jellyfish dump and jellyfish dump -c respectively."))
+        .arg(clap::Arg::with_name("histogram")
+             .long("histogram")
+             .conflicts_with("update")
+             .help("Output how many k-mers have each count, like jellyfish histo, \
+                  instead of the counts"))
+        .arg(clap::Arg::with_name("top")
+             .long("top")
+             .takes_value(true)
+             .value_name("N")
+             .conflicts_with_all(&["update", "histogram"])
+             .help("Output only the N most frequent k-mers, most frequent first, with \
+                  their counts and share of all counted k-mers of at least the minimum \
+                  count"))
+        .arg(clap::Arg::with_name("top_canonical")
+             .long("top-canonical")
+             .requires("top")
+             .help("Add the canonical form of each top k-mer, the lesser of it and its \
+                  reverse complement"))
+        .arg(clap::Arg::with_name("stats")
+             .long("stats")
+             .takes_value(true)
+             .value_name("FILE")
+             .help("Write a summary of the run to FILE: records per input, bases, \
+                  invalid characters, total and distinct k-mers, singletons, the \
+                  highest count and the time taken by each stage. The k-mer figures \
+                  cover the written counts, and those the options leave unknown are NA \
+                  (null in JSON): the total, singletons and highest count with \
+                  --only-presence, and all but the total with --unordered join output. \
+                  Counts streamed to the output are written while consolidating, so \
+                  output time is only what is held back until the end"))
+        .arg(clap::Arg::with_name("stats_format")
+             .long("stats-format")
+             .default_value("tsv")
+             .possible_values(&["tsv", "json"])
+             .help("The format of the --stats file"))
+        .arg(clap::Arg::with_name("import_jellyfish")
+             .long("import-jellyfish")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .value_name("DUMP")
+             .help("Merge in the counts from a Jellyfish text dump (either format)"))
+        .arg(clap::Arg::with_name("update")
+             .long("update")
+             .takes_value(true)
+             .value_name("DB")
+             .help("Merge the counts into the count database DB (created if missing) \
+                  instead of outputting them. All counts are stored regardless of \
+                  --min-count."))
         .get_matches();
 
+    match args.subcommand() {
+        ("sketch", Some(args)) => return sketch_command(args),
+        ("matrix", Some(args)) => return matrix_command(args),
+        ("profile", Some(args)) => return profile_command(args),
+        ("filter", Some(args)) => return filter_command(args),
+        ("unitigs", Some(args)) => return unitigs_command(args),
+        ("compare", Some(args)) => {
+            let paths = args.values_of("sketches").unwrap().map(|s| s.to_string()).collect::<Vec<_>>();
+            return exit_on_error(sketch::run_compare(&paths));
+        }
+        _ => {}
+    }
+
     let inputs = args.values_of("inputs")
         .map(|iter| {
             iter.map(|s| s.to_string())
                 .collect::<Vec<_>>()
         }).unwrap_or_else(|| Vec::new());
 
-    let threads = args.value_of("threads")
+    let threads = parse_threads(&args);
+
+    let kmer_lens = args.value_of("kmer_len")
         .unwrap()
-        .parse::<usize>()
-        .unwrap_or_else(|e| {
-            error!("Failed to parse thread count as a positive integer:");
-            error!("{}", e);
+        .split(',')
+        .map(|kmer_len| {
+            kmer_len.parse::<u8>().unwrap_or_else(|e| {
+                error!("Failed to parse k-mer length as a positive integer:");
+                error!("{}", e);
+                exit(1);
+            })
+        })
+        .collect::<Vec<_>>();
+    for (i, &kmer_len) in kmer_lens.iter().enumerate() {
+        if kmer_len < 1 {
+            error!("Kmer length must be at least 1");
             exit(1);
-        });
+        }
+        if kmer_lens[..i].contains(&kmer_len) {
+            error!("The kmer length {} is given more than once", kmer_len);
+            exit(1);
+        }
+    }
 
-    let kmer_len = args.value_of("kmer_len")
-        .unwrap()
-        .parse::<u8>()
-        .unwrap_or_else(|e| {
-            error!("Failed to parse k-mer length as a positive integer:");
-            error!("{}", e);
+    let translate = args.value_of("translate").map(|code| match code {
+        "standard" => translate::GeneticCode::Standard,
+        "bacterial" => translate::GeneticCode::Bacterial,
+        "mitochondrial" => translate::GeneticCode::Mitochondrial,
+        code @ _ => {
+            error!("Unknown genetic code {}", code);
             exit(1);
-        });
-    if kmer_len < 1 {
-        error!("Kmer length must be at least 1");
+        }
+    });
+    if translate.is_some() && args.occurrences_of("alphabet") > 0 &&
+       args.value_of("alphabet") != Some("protein") {
+        error!("Translated k-mers can only be counted with the protein alphabet");
         exit(1);
     }
-    if kmer_len > 32 {
-        error!("The kmer length {} is invalid as there is a limit of 32",
-               kmer_len);
-        exit(1);
+
+    let alphabet = match args.value_of("alphabet").unwrap() {
+        _ if translate.is_some() => alphabet::Kind::Protein,
+        "dna" => alphabet::Kind::Dna,
+        "rna" => alphabet::Kind::Rna,
+        "protein" => alphabet::Kind::Protein,
+        alphabet @ _ => {
+            error!("Unknown alphabet {}", alphabet);
+            exit(1);
+        }
+    };
+    for &kmer_len in kmer_lens.iter() {
+        if kmer_len > alphabet.max_kmer_length() {
+            error!("The kmer length {} is invalid as there is a limit of {} for this alphabet",
+                   kmer_len,
+                   alphabet.max_kmer_length());
+            exit(1);
+        }
     }
 
     let min_count = args.value_of("min_count")
@@ -130,9 +791,15 @@ fn main() {
             error!("{}", e);
             exit(1);
         });
+    let auto_join = args.values_of("join_methods")
+        .is_some_and(|mut iter| iter.any(|m| m == "auto"));
+    if auto_join && args.values_of("join_methods").unwrap().count() > 1 {
+        error!("The auto join method cannot be combined with other join methods");
+        exit(1);
+    }
     let join_methods = args.values_of("join_methods")
         .map(|iter| {
-            iter.map(|m| match m {
+            iter.filter(|&m| m != "auto").map(|m| match m {
                 "concat" => kmer_tree::JoinMethod::Concat,
                 "join" => kmer_tree::JoinMethod::Join,
                 "sort" => kmer_tree::JoinMethod::Sort,
@@ -145,28 +812,103 @@ fn main() {
         })
     .unwrap_or_else(|| Vec::new());
 
+    let format = match args.value_of("format").unwrap() {
+        "tsv" => output_counts::Format::Tsv,
+        "jellyfish" => output_counts::Format::JellyfishFasta,
+        "jellyfish-column" => output_counts::Format::JellyfishColumn,
+        format @ _ => {
+            error!("Unknown output format {}", format);
+            exit(1);
+        }
+    };
+
+    let input_format = match args.value_of("input_format").unwrap() {
+        "fasta" => parsers::Format::Fasta,
+        "sam" => parsers::Format::Sam { primary_only: args.is_present("primary_only") },
+        format @ _ => {
+            error!("Unknown input format {}", format);
+            exit(1);
+        }
+    };
+    if args.is_present("primary_only") && input_format == parsers::Format::Fasta {
+        error!("Only SAM inputs have secondary and supplementary alignments");
+        exit(1);
+    }
+
+    let stats_format = match args.value_of("stats_format").unwrap() {
+        "tsv" => stats::Format::Tsv,
+        "json" => stats::Format::Json,
+        format @ _ => {
+            error!("Unknown stats format {}", format);
+            exit(1);
+        }
+    };
+
+    let masking = if args.is_present("skip_soft_masked") {
+        get_kmers::Masking::SkipSoftMasked
+    } else if args.is_present("only_soft_masked") {
+        get_kmers::Masking::OnlySoftMasked
+    } else {
+        get_kmers::Masking::All
+    };
+
+    let top = args.value_of("top").map(|n| {
+        n.parse::<usize>().unwrap_or_else(|e| {
+            error!("Failed to parse the number of top k-mers as a positive integer:");
+            error!("{}", e);
+            exit(1);
+        })
+    });
+
+    let flush_threshold = args.value_of("flush_threshold")
+        .unwrap()
+        .parse::<usize>()
+        .unwrap_or_else(|e| {
+            error!("Failed to parse flush threshold as a positive integer:");
+            error!("{}", e);
+            exit(1);
+        });
+
+    let counting = match args.value_of("counting").unwrap() {
+        "auto" => runner::Counting::Auto,
+        "dense" => runner::Counting::Dense,
+        "tree" => runner::Counting::Tree,
+        counting @ _ => {
+            error!("Unknown counting method {}", counting);
+            exit(1);
+        }
+    };
+
     let runner_opts = runner::Options {
         inputs: inputs,
         stdin: args.is_present("stdin"),
-        kmer_len: KmerLength::new(kmer_len),
+        kmer_lens: kmer_lens,
+        output_prefix: args.value_of("output_prefix").map(|s| s.to_string()),
+        alphabet: alphabet,
         min_count: min_count,
         only_presence: args.is_present("only_presence"),
         threads: threads,
         mmap: args.is_present("mmap"),
+        input_format: input_format,
         join_methods: join_methods,
+        auto_join: auto_join,
+        unordered: args.is_present("unordered"),
+        update: args.value_of("update").map(|s| s.to_string()),
+        jellyfish_imports: args.values_of("import_jellyfish")
+            .map(|iter| iter.map(|s| s.to_string()).collect())
+            .unwrap_or_default(),
+        format: format,
+        histogram: args.is_present("histogram"),
+        top: top,
+        top_canonical: args.is_present("top_canonical"),
+        translate: translate,
+        masking: masking,
+        counting: counting,
+        flush_threshold: flush_threshold,
+        zero_counts: args.is_present("zero_counts"),
+        stats: args.value_of("stats").map(|s| s.to_string()),
+        stats_format: stats_format,
     };
     info!("Argument parsing complete");
-    if let Err(ref e) = runner::run(runner_opts) {
-        error!("{}", e);
-
-        for e in e.iter().skip(1) {
-            error!("Caused by: {}", e);
-        }
-
-        if let Some(backtrace) = e.backtrace() {
-            error!("Backtrace:\n{:?}", backtrace);
-        }
-
-        exit(2);
-    }
+    exit_on_error(runner::run(runner_opts));
 }
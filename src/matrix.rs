@@ -0,0 +1,199 @@
+//! Exact all-vs-all comparison of the distinct k-mers of many inputs
+
+use std::io;
+use std::io::Write;
+use std::io::BufWriter;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use errors::*;
+use alphabet;
+use alphabet::Alphabet;
+use kmer_length::KmerLength;
+use get_kmers;
+use pipeline;
+use parsers;
+use sketch;
+use sketch::ratio;
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum Format {
+    /// A line per pair of inputs
+    Tsv,
+    /// A square (relaxed) PHYLIP distance matrix
+    Phylip,
+}
+
+/// The options of the `matrix` subcommand
+pub struct Options {
+    pub inputs: Vec<String>,
+    pub kmer_len: u8,
+    pub alphabet: alphabet::Kind,
+    /// Compare nucleotide k-mers as they are, instead of their canonical form
+    pub stranded: bool,
+    pub format: Format,
+    pub threads: usize,
+    pub mmap: bool,
+}
+
+/// The sorted distinct k-mers of one input, canonical ones if `canonical` is set
+pub fn distinct_kmers<A: Alphabet>(path: &str,
+                                   kmer_len: KmerLength<A>,
+                                   canonical: bool,
+                                   threads: usize,
+                                   mmap: bool)
+                                   -> Result<Vec<u64>> {
+    let input = try!(pipeline::open(path.to_string(), mmap));
+    let states = (0..threads + 1)
+        .map(|_| (get_kmers::SliceKmers::new(kmer_len, get_kmers::Masking::All), Vec::new()))
+        .collect();
+    let (format, overlap) = (parsers::Format::Fasta, pipeline::Overlap::kmers(kmer_len));
+    let parsers = 1 + threads / 4;
+    let (states, _) = try!(pipeline::run(vec![input], format, overlap, parsers, states, |state, record, _| {
+            let (ref mut kmers, ref mut found) = *state;
+            if canonical {
+                kmers.for_each_canonical(record, |kmer| found.push(kmer));
+            } else {
+                kmers.for_each(record, |kmer| found.push(kmer));
+            }
+            Ok(())
+        })
+        .chain_err(|| format!("Failed to read the k-mers of {}", path)));
+    let mut kmers = Vec::new();
+    for (_, mut found) in states {
+        found.sort_unstable();
+        found.dedup();
+        kmers.extend(found);
+    }
+    kmers.sort_unstable();
+    kmers.dedup();
+    Ok(kmers)
+}
+
+/// The number of values in both of two sorted and deduplicated slices
+pub fn shared(a: &[u64], b: &[u64]) -> usize {
+    let (mut i, mut j, mut shared) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] < b[j] {
+            i += 1;
+        } else if a[i] > b[j] {
+            j += 1;
+        } else {
+            shared += 1;
+            i += 1;
+            j += 1;
+        }
+    }
+    shared
+}
+
+/// The shared k-mers of every pair of inputs, as a square matrix of rows.
+/// Rows are handed out to the threads one at a time.
+pub fn shared_matrix(sets: &[Vec<u64>], threads: usize) -> Vec<Vec<usize>> {
+    let n = sets.len();
+    let rows = Mutex::new(vec![Vec::new(); n]);
+    let next = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    if i >= n {
+                        break;
+                    }
+                    let row = (0..n)
+                        .map(|j| if j <= i { 0 } else { shared(&sets[i], &sets[j]) })
+                        .collect();
+                    rows.lock().unwrap()[i] = row;
+                }
+            });
+        }
+    });
+    let mut rows = rows.into_inner().unwrap();
+    for i in 0..n {
+        rows[i][i] = sets[i].len();
+        let lower = rows[..i].iter().map(|row| row[i]).collect::<Vec<_>>();
+        rows[i][..i].copy_from_slice(&lower);
+    }
+    rows
+}
+
+/// PHYLIP names end at whitespace
+fn phylip_name(name: &str) -> String {
+    name.chars().map(|c| if c.is_whitespace() { '_' } else { c }).collect()
+}
+
+pub fn write<W: Write>(mut stream: W,
+                       names: &[String],
+                       sets: &[Vec<u64>],
+                       shared: &[Vec<usize>],
+                       kmer_len: u8,
+                       format: Format)
+                       -> io::Result<()> {
+    let n = names.len();
+    let union = |i: usize, j: usize| sets[i].len() + sets[j].len() - shared[i][j];
+    match format {
+        Format::Tsv => {
+            try!(writeln!(stream, "#a\tb\tshared\tjaccard\tcontainment_a_in_b\tcontainment_b_in_a"));
+            for i in 0..n {
+                for j in i + 1..n {
+                    try!(writeln!(stream,
+                                  "{}\t{}\t{}\t{:.6}\t{:.6}\t{:.6}",
+                                  names[i],
+                                  names[j],
+                                  shared[i][j],
+                                  ratio(shared[i][j], union(i, j)),
+                                  ratio(shared[i][j], sets[i].len()),
+                                  ratio(shared[i][j], sets[j].len())));
+                }
+            }
+        }
+        Format::Phylip => {
+            try!(writeln!(stream, "{}", n));
+            for i in 0..n {
+                try!(write!(stream, "{}", phylip_name(&names[i])));
+                for (j, &count) in shared[i].iter().enumerate() {
+                    let distance = if i == j {
+                        0.0
+                    } else {
+                        sketch::mash_distance(ratio(count, union(i, j)), kmer_len)
+                    };
+                    try!(write!(stream, " {:.6}", distance));
+                }
+                try!(writeln!(stream));
+            }
+        }
+    }
+    stream.flush()
+}
+
+pub fn run(opts: Options) -> Result<()> {
+    match opts.alphabet {
+        alphabet::Kind::Dna => run_alphabet::<alphabet::Dna>(opts),
+        alphabet::Kind::Rna => run_alphabet::<alphabet::Rna>(opts),
+        alphabet::Kind::Protein => run_alphabet::<alphabet::Protein>(opts),
+    }
+}
+
+fn run_alphabet<A: Alphabet>(opts: Options) -> Result<()> {
+    let Options { inputs, kmer_len, alphabet: _, stranded, format, threads, mmap } = opts;
+    let kmer_len = KmerLength::<A>::new(kmer_len);
+    let canonical = A::COMPLEMENTED && !stranded;
+    let mut sets = Vec::new();
+    for path in inputs.iter() {
+        let kmers = try!(distinct_kmers(path, kmer_len, canonical, threads, mmap));
+        info!("Found {} distinct k-mers in {}", kmers.len(), path);
+        sets.push(kmers);
+    }
+    let shared = shared_matrix(&sets, threads + 1);
+    info!("Compared {} pairs of inputs", inputs.len() * inputs.len().saturating_sub(1) / 2);
+    let stdout = io::stdout();
+    write(BufWriter::new(stdout.lock()),
+          &inputs,
+          &sets,
+          &shared,
+          kmer_len.length(),
+          format)
+        .chain_err(|| "Failed to write the similarity matrix")
+}
@@ -2,33 +2,77 @@ use std::io::Write;
 use std::io::BufWriter;
 
 use errors::*;
+use alphabet::Alphabet;
 use kmer_length::KmerLength;
-use nucleotide::Nucleotide;
+use sink::Sink;
 
-pub fn output<T>(stream: T,
-                 counts: Vec<Option<(u64, u16)>>,
-                 kmer_len: KmerLength,
-                 min_count: u16)
-    where T: Write
-{
-    let mut stream = BufWriter::new(stream);
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum Format {
+    /// `KMER\tcount`, identical to `jellyfish dump -c -t`
+    Tsv,
+    /// `>count\nKMER`, identical to `jellyfish dump`
+    JellyfishFasta,
+    /// `KMER count`, identical to `jellyfish dump -c`
+    JellyfishColumn,
+}
+
+/// Decodes a k-mer into its text
+pub fn kmer_text<A: Alphabet>(mut kmer: u64, kmer_len: KmerLength<A>) -> Vec<u8> {
     let kmer_len = kmer_len.length() as usize;
-    for (mut kmer, count) in counts.into_iter().filter_map(|n| n) {
-        if count < min_count {
-            continue;
-        }
-        let mut kmer_str = vec![0; kmer_len + 1];
-        kmer_str[kmer_len] = b'\t';
-        for i in (0..kmer_len).rev() {
-            let nucleotide = Nucleotide::from_lower_bits(kmer as u8);
-            let chr = nucleotide.as_text_byte();
-            kmer_str[i] = chr;
-            kmer = kmer >> 2;
+    let mut text = vec![0; kmer_len];
+    let symbol_mask = (1 << A::BITS) - 1;
+    for i in (0..kmer_len).rev() {
+        text[i] = A::decode((kmer & symbol_mask) as u8);
+        kmer >>= A::BITS;
+    }
+    text
+}
+
+/// Writes k-mers and their counts as text
+pub struct TextSink<W: Write, A> {
+    stream: BufWriter<W>,
+    kmer_len: KmerLength<A>,
+    format: Format,
+}
+
+impl<W: Write, A: Alphabet> TextSink<W, A> {
+    pub fn new(stream: W, kmer_len: KmerLength<A>, format: Format) -> TextSink<W, A> {
+        TextSink {
+            stream: BufWriter::new(stream),
+            kmer_len: kmer_len,
+            format: format,
         }
-        stream.write(kmer_str.as_slice())
-            .and_then(|_| stream.write(count.to_string().as_bytes()))
-            .and_then(|_| stream.write(b"\n"))
+    }
+}
+
+impl<W: Write, A: Alphabet> Sink for TextSink<W, A> {
+    fn write(&mut self, kmer: u64, count: u16) -> Result<()> {
+        let kmer_str = kmer_text(kmer, self.kmer_len);
+        let count = count.to_string();
+        let stream = &mut self.stream;
+        let result = match self.format {
+            Format::Tsv => {
+                stream.write_all(kmer_str.as_slice())
+                    .and_then(|_| stream.write_all(b"\t"))
+                    .and_then(|_| stream.write_all(count.as_bytes()))
+            }
+            Format::JellyfishFasta => {
+                stream.write_all(b">")
+                    .and_then(|_| stream.write_all(count.as_bytes()))
+                    .and_then(|_| stream.write_all(b"\n"))
+                    .and_then(|_| stream.write_all(kmer_str.as_slice()))
+            }
+            Format::JellyfishColumn => {
+                stream.write_all(kmer_str.as_slice())
+                    .and_then(|_| stream.write_all(b" "))
+                    .and_then(|_| stream.write_all(count.as_bytes()))
+            }
+        };
+        result.and_then(|_| stream.write_all(b"\n"))
             .chain_err(|| "Failed to write k-mer to output stream")
-            .unwrap();
-        }
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.stream.flush().chain_err(|| "Failed to write k-mer to output stream")
+    }
 }
@@ -1 +1,12 @@
 pub mod multifasta;
+pub mod records;
+pub mod sam;
+
+/// The format of the inputs of the counting pipeline
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Format {
+    Fasta,
+    /// Reads stored in SAM, with or without their secondary and
+    /// supplementary alignments
+    Sam { primary_only: bool },
+}
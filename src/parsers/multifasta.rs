@@ -1,65 +1,65 @@
-use errors::*;
-use nucleotide::Nucleotide;
+use memchr::memchr;
 
-pub struct Section<'a, T: 'a> {
-    file: &'a mut T,
-    done: bool,
+/// The sections of a FASTA file, without their header lines.
+/// Sections borrow from the input and still contain their line breaks.
+pub struct Sections<'a> {
+    data: &'a [u8],
 }
 
-impl<'a, T: Iterator<Item = Result<u8>>> Iterator for Section<'a, T> {
-    type Item = Result<Nucleotide>;
+impl<'a> Sections<'a> {
+    pub fn new(data: &'a [u8]) -> Sections<'a> {
+        Sections { data: data }
+    }
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.done {
-            return None;
-        }
-        while let Some(c) = self.file.next() {
-            let c = match c {
-                Ok(c) => c,
-                Err(e) => return Some(Err(e)),
-            };
-            match c {
-                b' ' | b'\n' | b'\t' => continue,
-                b'>' => {
-                    self.done = true;
-                    return None;
-                }
-                _ => {
-                    if let Some(n) = Nucleotide::from_text_byte(c) {
-                        return Some(Ok(n));
-                    } else {
-                        warn!("Encountered invalid character in input multifasta: {}",
-                              c as char);
-                        continue;
-                    }
-                }
+impl<'a> Iterator for Sections<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        match split_record(self.data) {
+            Some(record) => {
+                self.data = record.rest.unwrap_or(&[]);
+                Some(record.seq)
+            }
+            None => {
+                self.data = &[];
+                None
             }
         }
-        None
     }
 }
 
-pub struct SectionReader<T> {
-    file: T,
+/// The first record of some FASTA data
+pub struct Split<'a> {
+    /// The header line without its marker or line break
+    pub header: &'a [u8],
+    /// The sequence, which still contains its line breaks
+    pub seq: &'a [u8],
+    /// The data after the `>` of the next record, if there is one
+    pub rest: Option<&'a [u8]>,
 }
 
-impl<T: Iterator<Item = Result<u8>>> SectionReader<T> {
-    pub fn new(file: T) -> SectionReader<T> {
-        SectionReader { file: file }
-    }
-
-    pub fn next_section<'a>(&'a mut self) -> Option<Result<Section<'a, T>>> {
-        loop {
-            match self.file.next() {
-                None => return None,
-                Some(Err(e)) => return Some(Err(e)),
-                Some(Ok(b'\n')) => break,
-                Some(_) => continue,
+/// Splits the first record off FASTA data which starts with its header line,
+/// after the `>`. Returns `None` if there is no whole header line.
+pub fn split_record<'a>(data: &'a [u8]) -> Option<Split<'a>> {
+    // Everything up to the first line break is the header
+    memchr(b'\n', data).map(|i| {
+        let (header, body) = (&data[..i], &data[i + 1..]);
+        match memchr(b'>', body) {
+            Some(end) => {
+                Split {
+                    header: header,
+                    seq: &body[..end],
+                    rest: Some(&body[end + 1..]),
+                }
+            }
+            None => {
+                Split {
+                    header: header,
+                    seq: body,
+                    rest: None,
+                }
             }
         }
-        Some(Ok(Section {
-            file: &mut self.file,
-            done: false,
-        }))
-    }
+    })
 }
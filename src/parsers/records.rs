@@ -0,0 +1,350 @@
+//! Whole records, with their headers, for commands which work read by read
+//! rather than counting every k-mer of the input together
+
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+
+use errors::*;
+use parsers::multifasta;
+
+#[derive(PartialEq, Eq, Debug, Default, Clone)]
+pub struct Record {
+    /// The header line without its marker or line break
+    pub header: Vec<u8>,
+    /// The sequence with its line breaks removed
+    pub seq: Vec<u8>,
+    /// The qualities of FASTQ records
+    pub qual: Option<Vec<u8>>,
+}
+
+impl Record {
+    /// The header up to the first whitespace
+    pub fn id(&self) -> &[u8] {
+        let end = self.header.iter().position(|c| c.is_ascii_whitespace()).unwrap_or(self.header.len());
+        &self.header[..end]
+    }
+
+    /// Writes the record as FASTQ if it has qualities, otherwise as FASTA
+    pub fn write<W: Write>(&self, stream: &mut W) -> io::Result<()> {
+        match self.qual {
+            Some(ref qual) => {
+                try!(stream.write_all(b"@"));
+                try!(stream.write_all(&self.header));
+                try!(stream.write_all(b"\n"));
+                try!(stream.write_all(&self.seq));
+                try!(stream.write_all(b"\n+\n"));
+                try!(stream.write_all(qual));
+            }
+            None => {
+                try!(stream.write_all(b">"));
+                try!(stream.write_all(&self.header));
+                try!(stream.write_all(b"\n"));
+                try!(stream.write_all(&self.seq));
+            }
+        }
+        stream.write_all(b"\n")
+    }
+}
+
+/// Reads a line without its line break, returns false at the end of input
+fn read_line<R: BufRead>(reader: &mut R, line: &mut Vec<u8>) -> Result<bool> {
+    line.clear();
+    if try!(reader.read_until(b'\n', line).chain_err(|| "Error reading input file")) == 0 {
+        return Ok(false);
+    }
+    while line.last().is_some_and(|&c| c == b'\n' || c == b'\r') {
+        line.pop();
+    }
+    Ok(true)
+}
+
+/// The fewest bytes of FASTA read at a time
+const READ_SIZE: usize = 64 * 1024;
+
+/// The records of a FASTA file, one at a time. They are split with
+/// `multifasta::split_record`, like the sections of the counting pipeline.
+pub struct FastaRecords<R> {
+    reader: R,
+    /// Input which has been read, of which the records from `start` on are
+    /// still to be parsed
+    buffer: Vec<u8>,
+    start: usize,
+    /// Whether the data from `start` on follows the `>` of a record
+    in_record: bool,
+    started: bool,
+    eof: bool,
+}
+
+impl<R: BufRead> FastaRecords<R> {
+    pub fn new(reader: R) -> FastaRecords<R> {
+        FastaRecords {
+            reader: reader,
+            buffer: Vec::new(),
+            start: 0,
+            in_record: false,
+            started: false,
+            eof: false,
+        }
+    }
+
+    /// Reads more input, at least as much as is left in the buffer so long
+    /// records are only searched a few times
+    fn fill(&mut self) -> Result<()> {
+        self.buffer.drain(..self.start);
+        self.start = 0;
+        let wanted = ::std::cmp::max(READ_SIZE, self.buffer.len());
+        let read = try!((&mut self.reader)
+            .take(wanted as u64)
+            .read_to_end(&mut self.buffer)
+            .chain_err(|| "Error reading input file"));
+        self.eof = read < wanted;
+        Ok(())
+    }
+
+    fn next_record(&mut self) -> Result<Option<Record>> {
+        if !self.started {
+            self.started = true;
+            loop {
+                let data = &self.buffer[self.start..];
+                if let Some(i) = data.iter().position(|c| !c.is_ascii_whitespace()) {
+                    if data[i] != b'>' {
+                        bail!("FASTA input does not start with a > header");
+                    }
+                    self.start += i + 1;
+                    self.in_record = true;
+                    break;
+                }
+                self.start = self.buffer.len();
+                if self.eof {
+                    return Ok(None);
+                }
+                try!(self.fill());
+            }
+        }
+        if !self.in_record {
+            return Ok(None);
+        }
+        loop {
+            let (record, rest) = {
+                let data = &self.buffer[self.start..];
+                match multifasta::split_record(data) {
+                    Some(split) if split.rest.is_some() || self.eof => {
+                        ((split.header, split.seq), split.rest.map(|rest| rest.len()))
+                    }
+                    // A last header line without a line break
+                    None if self.eof => ((data, &[][..]), None),
+                    _ => {
+                        try!(self.fill());
+                        continue;
+                    }
+                }
+            };
+            let (header, seq) = record;
+            let header = match header.last() {
+                Some(&b'\r') => &header[..header.len() - 1],
+                _ => header,
+            };
+            let record = Record {
+                header: header.to_vec(),
+                seq: seq.iter().cloned().filter(|c| !c.is_ascii_whitespace()).collect(),
+                qual: None,
+            };
+            self.in_record = rest.is_some();
+            self.start = self.buffer.len() - rest.unwrap_or(0);
+            return Ok(Some(record));
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for FastaRecords<R> {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Result<Record>> {
+        match self.next_record() {
+            Ok(record) => record.map(Ok),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// The records of a FASTQ file, one at a time. Each record must be on four
+/// lines, as every current sequencer writes them.
+pub struct FastqRecords<R> {
+    reader: R,
+    line: Vec<u8>,
+}
+
+impl<R: BufRead> FastqRecords<R> {
+    pub fn new(reader: R) -> FastqRecords<R> {
+        FastqRecords {
+            reader: reader,
+            line: Vec::new(),
+        }
+    }
+
+    fn next_record(&mut self) -> Result<Option<Record>> {
+        loop {
+            if !try!(read_line(&mut self.reader, &mut self.line)) {
+                return Ok(None);
+            }
+            if !self.line.is_empty() {
+                break;
+            }
+        }
+        if self.line[0] != b'@' {
+            bail!("FASTQ record does not start with an @ header");
+        }
+        let header = self.line[1..].to_vec();
+        let mut lines = Vec::new();
+        for _ in 0..3 {
+            if !try!(read_line(&mut self.reader, &mut self.line)) {
+                bail!("FASTQ record {} is truncated", String::from_utf8_lossy(&header));
+            }
+            lines.push(self.line.clone());
+        }
+        let qual = lines.pop().unwrap();
+        if lines[1].first() != Some(&b'+') {
+            bail!("FASTQ record {} has no + separator line", String::from_utf8_lossy(&header));
+        }
+        let seq = lines.swap_remove(0);
+        if seq.len() != qual.len() {
+            bail!("FASTQ record {} has {} bases but {} qualities",
+                  String::from_utf8_lossy(&header),
+                  seq.len(),
+                  qual.len());
+        }
+        Ok(Some(Record {
+            header: header,
+            seq: seq,
+            qual: Some(qual),
+        }))
+    }
+}
+
+impl<R: BufRead> Iterator for FastqRecords<R> {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Result<Record>> {
+        match self.next_record() {
+            Ok(record) => record.map(Ok),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Reads FASTQ if the first character which isn't whitespace is `@`,
+/// otherwise FASTA
+pub fn from_reader<R: BufRead + 'static>(mut reader: R) -> Result<Box<Iterator<Item = Result<Record>>>> {
+    let fastq = loop {
+        let (first, skipped) = {
+            let buf = try!(reader.fill_buf().chain_err(|| "Error reading input file"));
+            if buf.is_empty() {
+                break false;
+            }
+            match buf.iter().position(|c| !c.is_ascii_whitespace()) {
+                Some(i) => (Some(buf[i]), i),
+                None => (None, buf.len()),
+            }
+        };
+        reader.consume(skipped);
+        if let Some(first) = first {
+            break first == b'@';
+        }
+    };
+    Ok(if fastq {
+        Box::new(FastqRecords::new(reader))
+    } else {
+        Box::new(FastaRecords::new(reader))
+    })
+}
+
+/// Opens a FASTA or FASTQ file as records
+pub fn open(path: &str) -> Result<Box<Iterator<Item = Result<Record>>>> {
+    let file = try!(File::open(path).chain_err(|| "Failed to open input file"));
+    from_reader(BufReader::new(file)).chain_err(|| format!("Failed to read {}", path))
+}
+
+/// Where the reads of a command which works read by read come from
+pub enum Inputs {
+    /// Unpaired reads
+    Single(Vec<String>),
+    /// Files of pairs, with each read followed by its mate
+    Interleaved(Vec<String>),
+    /// The first and second mates of each pair, in the same order
+    Paired(String, String),
+}
+
+/// The name of a read without the `/1` or `/2` suffix of older Illumina data,
+/// which is the same for both mates of a pair
+pub fn mate_name(id: &[u8]) -> &[u8] {
+    match id.len() {
+        len if len > 2 && id[len - 2] == b'/' && (id[len - 1] == b'1' || id[len - 1] == b'2') => {
+            &id[..len - 2]
+        }
+        _ => id,
+    }
+}
+
+/// Pairs up the next mates read, checking their names match
+fn mates(first: Option<Result<Record>>, second: Option<Result<Record>>) -> Result<Option<[Record; 2]>> {
+    let pair = match (first, second) {
+        (None, None) => return Ok(None),
+        (Some(first), Some(second)) => [try!(first), try!(second)],
+        (Some(first), None) | (None, Some(first)) => {
+            let first = try!(first);
+            bail!("Read {} has no mate", String::from_utf8_lossy(first.id()));
+        }
+    };
+    if mate_name(pair[0].id()) != mate_name(pair[1].id()) {
+        bail!("Mates {} and {} have different names",
+              String::from_utf8_lossy(pair[0].id()),
+              String::from_utf8_lossy(pair[1].id()));
+    }
+    Ok(Some(pair))
+}
+
+/// Calls `f` with each read, or both mates of each pair
+pub fn for_each_fragment<F>(inputs: &Inputs, mut f: F) -> Result<()>
+    where F: FnMut(&[Record]) -> Result<()>
+{
+    match *inputs {
+        Inputs::Single(ref paths) => {
+            for path in paths.iter() {
+                for record in try!(open(path)) {
+                    let record = try!(record.chain_err(|| format!("Failed to read {}", path)));
+                    try!(f(&[record]));
+                }
+            }
+        }
+        Inputs::Interleaved(ref paths) => {
+            for path in paths.iter() {
+                let mut records = try!(open(path));
+                loop {
+                    let pair = try!(mates(records.next(), records.next())
+                        .chain_err(|| format!("Failed to read {}", path)));
+                    match pair {
+                        Some(pair) => try!(f(&pair)),
+                        None => break,
+                    }
+                }
+            }
+        }
+        Inputs::Paired(ref first_path, ref second_path) => {
+            let mut first = try!(open(first_path));
+            let mut second = try!(open(second_path));
+            loop {
+                let pair = try!(mates(first.next(), second.next())
+                    .chain_err(|| format!("Failed to read pairs from {} and {}", first_path, second_path)));
+                match pair {
+                    Some(pair) => try!(f(&pair)),
+                    None => break,
+                }
+            }
+        }
+    }
+    Ok(())
+}
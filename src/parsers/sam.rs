@@ -0,0 +1,100 @@
+use std::borrow::Cow;
+
+use memchr::memchr;
+
+use errors::*;
+
+/// The read is reverse complemented
+const REVERSE: u16 = 0x10;
+/// Another alignment of the read is the primary one
+const SECONDARY: u16 = 0x100;
+/// Part of a chimeric alignment other than the representative one
+const SUPPLEMENTARY: u16 = 0x800;
+
+fn complement(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        b'a' => b't',
+        b'c' => b'g',
+        b'g' => b'c',
+        b't' => b'a',
+        other => other,
+    }
+}
+
+/// The reads of SAM alignment records, taken from their SEQ field. Header
+/// lines and records without a stored sequence are skipped. Reads aligned
+/// to the reverse strand are turned back into the sequenced orientation.
+pub struct Sections<'a> {
+    data: &'a [u8],
+    /// Skip secondary and supplementary alignments, so each read is seen once
+    primary_only: bool,
+}
+
+impl<'a> Sections<'a> {
+    pub fn new(data: &'a [u8], primary_only: bool) -> Sections<'a> {
+        Sections {
+            data: data,
+            primary_only: primary_only,
+        }
+    }
+
+    fn next_line(&mut self) -> Option<&'a [u8]> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let (line, rest) = match memchr(b'\n', self.data) {
+            Some(i) => (&self.data[..i], &self.data[i + 1..]),
+            None => (self.data, &[][..]),
+        };
+        self.data = rest;
+        Some(if line.last() == Some(&b'\r') { &line[..line.len() - 1] } else { line })
+    }
+
+    /// The read of an alignment line, or `None` if it is skipped
+    fn parse(&self, line: &'a [u8]) -> Result<Option<Cow<'a, [u8]>>> {
+        let mut fields = line.split(|&c| c == b'\t');
+        let name = fields.next().unwrap();
+        let flag = try!(fields.next()
+            .and_then(|flag| ::std::str::from_utf8(flag).ok())
+            .and_then(|flag| flag.parse::<u16>().ok())
+            .ok_or_else(|| {
+                format!("SAM record {} has no valid FLAG", String::from_utf8_lossy(name))
+            }));
+        // SEQ is the tenth field
+        let seq = try!(fields.nth(7)
+            .ok_or_else(|| format!("SAM record {} has no SEQ", String::from_utf8_lossy(name))));
+        if self.primary_only && flag & (SECONDARY | SUPPLEMENTARY) != 0 {
+            return Ok(None);
+        }
+        if seq == b"*" {
+            return Ok(None);
+        }
+        Ok(Some(if flag & REVERSE != 0 {
+            Cow::Owned(seq.iter().rev().map(|&base| complement(base)).collect())
+        } else {
+            Cow::Borrowed(seq)
+        }))
+    }
+}
+
+impl<'a> Iterator for Sections<'a> {
+    type Item = Result<Cow<'a, [u8]>>;
+
+    fn next(&mut self) -> Option<Result<Cow<'a, [u8]>>> {
+        while let Some(line) = self.next_line() {
+            if line.is_empty() || line[0] == b'@' {
+                continue;
+            }
+            match self.parse(line) {
+                Ok(Some(seq)) => return Some(Ok(seq)),
+                Ok(None) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,446 @@
+use std::io::Read;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use memchr::{memchr, memrchr};
+
+use errors::*;
+use alphabet::Alphabet;
+use kmer_length::KmerLength;
+use parsers;
+use parsers::Format;
+use readers;
+use readers::mmap::Map;
+
+/// Bytes read from an input at a time
+const BLOCK_SIZE: usize = 4 * 1024 * 1024;
+/// Items each channel holds per thread receiving from it
+const QUEUE_PER_THREAD: usize = 2;
+
+pub enum Input {
+    Stream(Box<Read + Send>),
+    Mapped(Map),
+}
+
+/// Opens an input file, memory mapping it if `mmap` is set
+pub fn open(path: String, mmap: bool) -> Result<Input> {
+    if mmap {
+        readers::mmap::open(path).map(Input::Mapped)
+    } else {
+        readers::file::open(path).map(|file| Input::Stream(Box::new(file)))
+    }
+}
+
+enum Data {
+    Owned(Vec<u8>),
+    Mapped(Arc<Map>, Range<usize>),
+}
+
+/// Bytes of one input which end at a record boundary, or a line break of a
+/// FASTA record too long for one block
+struct Block {
+    /// The index of the input the block is from
+    input: usize,
+    /// The block starts in the sequence of a record split from the last block
+    continued: bool,
+    data: Data,
+}
+
+impl Block {
+    fn bytes(&self) -> &[u8] {
+        match self.data {
+            Data::Owned(ref data) => data,
+            Data::Mapped(ref map, ref range) => &map.as_slice()[range.clone()],
+        }
+    }
+}
+
+/// What the stages other than counting saw
+pub struct Summary {
+    /// The number of records of each input
+    pub records: Vec<u64>,
+    /// Time spent reading streams. Memory maps are read as they are counted.
+    pub read_time: Duration,
+}
+
+/// The records of a block, as ranges of it
+pub struct Records {
+    block: Block,
+    sections: Vec<Range<usize>>,
+}
+
+impl Records {
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = &'a [u8]> + 'a {
+        let bytes = self.block.bytes();
+        self.sections.iter().map(move |range| &bytes[range.clone()])
+    }
+}
+
+/// Finds the last place in `data` after `from` where a block can end, which
+/// is before a `>` for FASTA and after a line break for SAM
+fn last_boundary(data: &[u8], from: usize, format: Format) -> Option<usize> {
+    match format {
+        Format::Fasta => memrchr(b'>', &data[from..]).map(|i| from + i),
+        Format::Sam { .. } => memrchr(b'\n', &data[from..]).map(|i| from + i + 1),
+    }
+}
+
+/// Finds the first place in `data` where a block can end
+fn first_boundary(data: &[u8], format: Format) -> Option<usize> {
+    match format {
+        Format::Fasta => memchr(b'>', data),
+        Format::Sam { .. } => memchr(b'\n', data).map(|i| i + 1),
+    }
+}
+
+/// How much the pieces of a FASTA record too long for one block overlap
+#[derive(Clone, Copy)]
+pub struct Overlap {
+    /// One less than the symbols a k-mer spans, so each k-mer is in exactly
+    /// one piece
+    pub symbols: usize,
+    /// Whether a byte takes up a place in a k-mer. Other bytes, like line
+    /// breaks and invalid characters, are skipped over by k-mers.
+    pub is_symbol: fn(u8) -> bool,
+}
+
+fn is_symbol<A: Alphabet>(c: u8) -> bool {
+    A::encode(c).is_some()
+}
+
+impl Overlap {
+    /// The overlap for k-mers of an alphabet
+    pub fn kmers<A: Alphabet>(kmer_len: KmerLength<A>) -> Overlap {
+        Overlap {
+            symbols: kmer_len.length() as usize - 1,
+            is_symbol: is_symbol::<A>,
+        }
+    }
+
+    /// Drops symbols from the start of a piece which overlaps the last one
+    /// by `overlap` symbols, so it only overlaps by `keep`
+    pub fn trim<'a>(&self, text: &'a [u8], overlap: usize, keep: usize) -> &'a [u8] {
+        let mut dropped = 0;
+        let mut start = 0;
+        while dropped + keep < overlap && start < text.len() {
+            if (self.is_symbol)(text[start]) {
+                dropped += 1;
+            }
+            start += 1;
+        }
+        &text[start..]
+    }
+}
+
+/// Where a FASTA record which fills a whole block is split: after the last
+/// line break of its sequence, or at the end of the block if there is none.
+/// Returns the end of the block and where the next one starts, early enough
+/// that k-mers across the split are still counted once.
+fn split_record(data: &[u8], continued: bool, overlap: Overlap) -> Option<(usize, usize)> {
+    let seq_start = if continued { 0 } else { memchr(b'\n', data).map_or(data.len(), |i| i + 1) };
+    let end = match memrchr(b'\n', &data[seq_start..]) {
+        // Very long lines are split too, rather than making a tiny block
+        Some(i) if seq_start + i + 1 > data.len() / 2 => seq_start + i + 1,
+        _ => data.len(),
+    };
+    let mut next = end;
+    let mut symbols = 0;
+    while symbols < overlap.symbols {
+        if next <= seq_start {
+            return None;
+        }
+        next -= 1;
+        if (overlap.is_symbol)(data[next]) {
+            symbols += 1;
+        }
+    }
+    if next <= seq_start {
+        return None;
+    }
+    Some((end, next))
+}
+
+/// Splits a stream into blocks, reading until a block has a boundary or a
+/// record fills a whole block
+fn read_stream(index: usize,
+               mut input: Box<Read + Send>,
+               format: Format,
+               overlap: Overlap,
+               blocks: &SyncSender<Result<Block>>,
+               read_time: &mut Duration)
+               -> bool {
+    let block = |data, continued| {
+        Block {
+            input: index,
+            continued: continued,
+            data: Data::Owned(data),
+        }
+    };
+    let mut data = Vec::new();
+    let mut continued = false;
+    loop {
+        let start = data.len();
+        let started = Instant::now();
+        let read = input.by_ref().take(BLOCK_SIZE as u64).read_to_end(&mut data);
+        *read_time += started.elapsed();
+        match read {
+            Err(e) => {
+                let _ = blocks.send(Err(e).chain_err(|| "Error reading input file"));
+                return false;
+            }
+            Ok(0) => return data.is_empty() || blocks.send(Ok(block(data, continued))).is_ok(),
+            Ok(_) => {}
+        }
+        // A `>` at the very start would make an empty block
+        if let Some(boundary) = last_boundary(&data, ::std::cmp::max(start, 1), format) {
+            let rest = data.split_off(boundary);
+            if blocks.send(Ok(block(data, continued))).is_err() {
+                return false;
+            }
+            data = rest;
+            continued = false;
+        } else if format == Format::Fasta && data.len() >= BLOCK_SIZE {
+            if let Some((end, next)) = split_record(&data, continued, overlap) {
+                let rest = data[next..].to_vec();
+                data.truncate(end);
+                if blocks.send(Ok(block(data, continued))).is_err() {
+                    return false;
+                }
+                data = rest;
+                continued = true;
+            }
+        }
+    }
+}
+
+/// Splits a memory map into blocks without copying it
+fn read_map(index: usize,
+            map: Map,
+            format: Format,
+            overlap: Overlap,
+            blocks: &SyncSender<Result<Block>>)
+            -> bool {
+    let map = Arc::new(map);
+    let len = map.as_slice().len();
+    let mut start = 0;
+    let mut continued = false;
+    while start < len {
+        let (end, next, next_continued) = {
+            let data = map.as_slice();
+            let limit = ::std::cmp::min(start + BLOCK_SIZE, len);
+            let split = || if format == Format::Fasta {
+                split_record(&data[start..limit], continued, overlap)
+            } else {
+                None
+            };
+            if limit == len {
+                (len, len, false)
+            } else if let Some(boundary) = last_boundary(&data[..limit], start + 1, format) {
+                (boundary, boundary, false)
+            } else if let Some((end, next)) = split() {
+                (start + end, start + next, true)
+            } else {
+                let end = first_boundary(&data[limit..], format).map_or(len, |i| limit + i);
+                (end, end, false)
+            }
+        };
+        let block = Block {
+            input: index,
+            continued: continued,
+            data: Data::Mapped(map.clone(), start..end),
+        };
+        if blocks.send(Ok(block)).is_err() {
+            return false;
+        }
+        start = next;
+        continued = next_continued;
+    }
+    true
+}
+
+/// Reads every input into blocks. A block ends just before a `>`, or after a
+/// line break for SAM, so no record is split between blocks, and grows until
+/// one is found. FASTA records longer than a block are the exception, they
+/// are split at a line break so a block never grows much past `BLOCK_SIZE`.
+/// Returns the time spent reading.
+fn read_blocks(inputs: Vec<Input>,
+               format: Format,
+               overlap: Overlap,
+               blocks: SyncSender<Result<Block>>)
+               -> Duration {
+    let mut read_time = Duration::from_secs(0);
+    for (index, input) in inputs.into_iter().enumerate() {
+        let more = match input {
+            Input::Stream(stream) => read_stream(index, stream, format, overlap, &blocks, &mut read_time),
+            Input::Mapped(map) => read_map(index, map, format, overlap, &blocks),
+        };
+        if !more {
+            break;
+        }
+    }
+    read_time
+}
+
+fn parse_block(block: Block, format: Format) -> Result<Records> {
+    let primary_only = match format {
+        Format::Fasta => {
+            let sections = {
+                let bytes = block.bytes();
+                let base = bytes.as_ptr() as usize;
+                // A continued block starts with the rest of a sequence, without a header
+                let mut sections = Vec::new();
+                let records = if block.continued {
+                    let end = memchr(b'>', bytes).unwrap_or(bytes.len());
+                    sections.push(0..end);
+                    &bytes[end..]
+                } else {
+                    bytes
+                };
+                sections.extend(parsers::multifasta::Sections::new(records).map(|section| {
+                    let start = section.as_ptr() as usize - base;
+                    start..start + section.len()
+                }));
+                sections
+            };
+            return Ok(Records {
+                block: block,
+                sections: sections,
+            });
+        }
+        Format::Sam { primary_only } => primary_only,
+    };
+    // Reverse complemented reads don't borrow from the block, so the reads
+    // are copied into a block of their own
+    let mut data = Vec::with_capacity(block.bytes().len());
+    let mut sections = Vec::new();
+    for seq in parsers::sam::Sections::new(block.bytes(), primary_only) {
+        let seq = try!(seq);
+        sections.push(data.len()..data.len() + seq.len());
+        data.extend_from_slice(&seq);
+    }
+    Ok(Records {
+        block: Block {
+            input: block.input,
+            continued: false,
+            data: Data::Owned(data),
+        },
+        sections: sections,
+    })
+}
+
+/// Returns the number of records parsed from each input.
+fn parse_blocks(inputs: usize,
+                format: Format,
+                blocks: &Mutex<Receiver<Result<Block>>>,
+                records: SyncSender<Result<Records>>)
+                -> Vec<u64> {
+    let mut counts = vec![0; inputs];
+    loop {
+        let block = match blocks.lock().unwrap().recv() {
+            Ok(block) => block,
+            Err(_) => break,
+        };
+        let parsed = block.and_then(|block| parse_block(block, format));
+        if let Ok(ref parsed) = parsed {
+            // The rest of a split record isn't a record of its own
+            counts[parsed.block.input] += parsed.sections.len() as u64 - parsed.block.continued as u64;
+        }
+        if records.send(parsed).is_err() {
+            break;
+        }
+    }
+    counts
+}
+
+/// Runs `count` over every record, with a reader thread, `parsers` parser
+/// threads and a counting thread per state, connected by bounded channels.
+/// Each counting thread owns one of the states, which are returned once
+/// every input has been counted.
+///
+/// FASTA records longer than a block are counted in pieces which overlap by
+/// `overlap`. `count` is given the number of symbols at the start of each
+/// record which overlap the last piece, so k-mers ending within them, which
+/// were counted with that piece, can be left out.
+pub fn run<S, F>(inputs: Vec<Input>,
+                 format: Format,
+                 overlap: Overlap,
+                 parsers: usize,
+                 states: Vec<S>,
+                 count: F)
+                 -> Result<(Vec<S>, Summary)>
+    where S: Send,
+          F: Fn(&mut S, &[u8], usize) -> Result<()> + Sync
+{
+    let (block_sender, blocks) = sync_channel(parsers * QUEUE_PER_THREAD);
+    let (records_sender, records) = sync_channel(states.len() * QUEUE_PER_THREAD);
+    // Each stage shares its receiver, so once every thread of a stage is
+    // gone, even by panicking, the stage before it can no longer block
+    let blocks = Arc::new(Mutex::new(blocks));
+    let records = Arc::new(Mutex::new(records));
+    let count = &count;
+    let input_count = inputs.len();
+
+    thread::scope(|scope| {
+        let reader = scope.spawn(move || read_blocks(inputs, format, overlap, block_sender));
+        let parsers = (0..parsers)
+            .map(|_| {
+                let records_sender = records_sender.clone();
+                let blocks = blocks.clone();
+                scope.spawn(move || parse_blocks(input_count, format, &blocks, records_sender))
+            })
+            .collect::<Vec<_>>();
+        drop(records_sender);
+        drop(blocks);
+
+        let counters = states.into_iter()
+            .map(|mut state| {
+                let records = records.clone();
+                scope.spawn(move || {
+                    // After an error the channel is still drained, so the
+                    // earlier stages can finish
+                    let mut result = Ok(());
+                    loop {
+                        let batch = match records.lock().unwrap().recv() {
+                            Ok(batch) => batch,
+                            Err(_) => break,
+                        };
+                        if result.is_err() {
+                            continue;
+                        }
+                        result = batch.and_then(|batch| {
+                            for (i, record) in batch.iter().enumerate() {
+                                let skip = if i == 0 && batch.block.continued { overlap.symbols } else { 0 };
+                                try!(count(&mut state, record, skip));
+                            }
+                            Ok(())
+                        });
+                    }
+                    result.map(|_| state)
+                })
+            })
+            .collect::<Vec<_>>();
+        drop(records);
+        let states = try!(counters.into_iter()
+            .map(|counter| {
+                match counter.join() {
+                    Ok(state) => state,
+                    Err(_) => bail!("A k-mer counting thread panicked"),
+                }
+            })
+            .collect::<Result<Vec<_>>>());
+        let mut summary = Summary {
+            records: vec![0; input_count],
+            read_time: try!(reader.join().map_err(|_| "The input reading thread panicked")),
+        };
+        for parser in parsers {
+            let records = try!(parser.join().map_err(|_| "A parsing thread panicked"));
+            for (total, count) in summary.records.iter_mut().zip(records) {
+                *total += count;
+            }
+        }
+        Ok((states, summary))
+    })
+}
@@ -0,0 +1,137 @@
+use std::collections::HashSet;
+
+use kmer_tree::JoinMethod;
+use kmer_tree::Node;
+
+/// Roughly how many k-mers are sampled to estimate the duplication rate
+const SAMPLE_SIZE: u64 = 64 * 1024;
+/// Below this fraction of distinct k-mers, merging early is worth it
+const HIGH_DUPLICATION: f64 = 0.5;
+/// Children at least this large are worth sorting (and shrinking) on their own
+const SORT_CHILD_MIN_ENTRIES: usize = 1024 * 1024;
+
+#[derive(Default)]
+struct LevelStats {
+    branches: usize,
+    leaves: usize,
+    entries: usize,
+}
+
+fn collect_stats(node: &Node, depth: usize, levels: &mut Vec<LevelStats>) {
+    if levels.len() <= depth {
+        levels.push(LevelStats::default());
+    }
+    match *node {
+        Node::Leaf(ref leaf) => {
+            levels[depth].leaves += 1;
+            levels[depth].entries += leaf.counts.len();
+        }
+        Node::Branch(ref children) => {
+            levels[depth].branches += 1;
+            for child in children {
+                collect_stats(child, depth + 1, levels);
+            }
+        }
+    }
+}
+
+/// Estimates the fraction of k-mer occurrences which are distinct.
+/// K-mers are sampled by hash, so every copy of a sampled k-mer is seen.
+fn distinct_ratio(node: &Node, occurrences: u64) -> f64 {
+    fn sample(node: &Node, threshold: u64, seen: &mut HashSet<u64>, sampled: &mut u64) {
+        match *node {
+            Node::Leaf(ref leaf) => {
+                for (kmer, count) in leaf.counts.iter() {
+                    if kmer.wrapping_mul(0x9e3779b97f4a7c15) >> 48 < threshold {
+                        seen.insert(kmer);
+                        *sampled += count as u64;
+                    }
+                }
+            }
+            Node::Branch(ref children) => {
+                for child in children {
+                    sample(child, threshold, seen, sampled);
+                }
+            }
+        }
+    }
+    let threshold = if occurrences <= SAMPLE_SIZE {
+        1 << 16
+    } else {
+        ::std::cmp::max(1, (1 << 16) * SAMPLE_SIZE / occurrences)
+    };
+    let mut seen = HashSet::new();
+    let mut sampled = 0;
+    sample(node, threshold, &mut seen, &mut sampled);
+    if sampled == 0 {
+        1.0
+    } else {
+        seen.len() as f64 / sampled as f64
+    }
+}
+
+fn occurrences(node: &Node) -> u64 {
+    match *node {
+        Node::Leaf(ref leaf) => leaf.counts.iter().map(|(_, count)| count as u64).sum(),
+        Node::Branch(ref children) => children.iter().map(occurrences).sum(),
+    }
+}
+
+fn method_name(method: &JoinMethod) -> &'static str {
+    match *method {
+        JoinMethod::Concat => "concat",
+        JoinMethod::Join => "join",
+        JoinMethod::Sort => "sort",
+    }
+}
+
+/// Picks a join method for each depth of the tree. The top level is sorted
+/// when `sorted` output is wanted, so it is merged. Otherwise, and below it,
+/// large children are sorted on
+/// their own when that will shrink them a lot, or hash joined when there is
+/// only one thread for the sort to use; otherwise they are concatenated
+/// so the parallel sort above gets a single large leaf.
+pub fn plan(root: &Node, threads: usize, sorted: bool) -> Vec<JoinMethod> {
+    let mut levels = Vec::new();
+    collect_stats(root, 0, &mut levels);
+    let ratio = distinct_ratio(root, occurrences(root));
+    let duplicated = ratio < HIGH_DUPLICATION;
+
+    let mut methods = Vec::new();
+    for depth in 0..levels.len() {
+        if levels[depth].branches == 0 {
+            break;
+        }
+        if depth == 0 && sorted {
+            methods.push(JoinMethod::Sort);
+            continue;
+        }
+        let children = levels.get(depth + 1).map_or(0, |l| l.branches + l.leaves);
+        let entries_below = levels[depth + 1..].iter().map(|l| l.entries).sum::<usize>();
+        let child_entries = entries_below / ::std::cmp::max(children, 1);
+        let method = if !duplicated || child_entries < SORT_CHILD_MIN_ENTRIES {
+            JoinMethod::Concat
+        } else if threads <= 1 {
+            JoinMethod::Join
+        } else {
+            JoinMethod::Sort
+        };
+        methods.push(method);
+    }
+
+    // A hash join doesn't care about order, so sorting below one is wasted
+    let mut below_join = false;
+    for method in methods.iter_mut() {
+        if below_join && *method == JoinMethod::Sort {
+            *method = JoinMethod::Concat;
+        }
+        below_join = below_join || *method == JoinMethod::Join;
+    }
+
+    info!("Planned join methods {} ({} k-mers, {:.1}% distinct, {} threads)",
+          methods.iter().map(method_name).collect::<Vec<_>>().join(","),
+          levels.iter().map(|l| l.entries).sum::<usize>(),
+          ratio * 100.0,
+          threads);
+    methods
+}
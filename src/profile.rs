@@ -0,0 +1,104 @@
+//! Per-read profiles of how often each k-mer of a read was seen in a saved
+//! count database, for finding reads with erroneous or repetitive k-mers
+
+use std::io;
+use std::io::Write;
+use std::io::BufWriter;
+
+use errors::*;
+use alphabet;
+use alphabet::Alphabet;
+use database;
+use get_kmers;
+use parsers::records;
+use parsers::records::Record;
+
+/// The options of the `profile` subcommand
+pub struct Options {
+    pub inputs: records::Inputs,
+    pub database: String,
+    pub alphabet: alphabet::Kind,
+    /// Counts below this are reported as a fraction of each read's k-mers
+    pub threshold: u16,
+}
+
+/// Summary values of the counts of one read's k-mers
+#[derive(PartialEq, Debug)]
+pub struct Summary {
+    pub min: u16,
+    /// The lower middle count for an even number of k-mers
+    pub median: u16,
+    /// The fraction of k-mers with a count below the threshold
+    pub below: f64,
+}
+
+/// Summarizes a profile, or returns `None` if the read has no k-mers
+pub fn summarize(profile: &[u16], threshold: u16) -> Option<Summary> {
+    if profile.is_empty() {
+        return None;
+    }
+    let mut sorted = profile.to_vec();
+    sorted.sort_unstable();
+    let below = sorted.iter().take_while(|&&count| count < threshold).count();
+    Some(Summary {
+        min: sorted[0],
+        median: sorted[(sorted.len() - 1) / 2],
+        below: below as f64 / sorted.len() as f64,
+    })
+}
+
+/// Writes the profile of a read as `id, k-mers, min, median, fraction below
+/// the threshold, counts`, where the counts are comma separated in the order
+/// of the read's k-mers. A pair has one profile, with the first mate's
+/// k-mers first, named without the mates' `/1` or `/2` suffix.
+pub fn write_profile<W: Write>(stream: &mut W,
+                               fragment: &[Record],
+                               profile: &[u16],
+                               threshold: u16)
+                               -> io::Result<()> {
+    let id = fragment[0].id();
+    try!(stream.write_all(if fragment.len() > 1 { records::mate_name(id) } else { id }));
+    match summarize(profile, threshold) {
+        Some(summary) => {
+            try!(write!(stream,
+                        "\t{}\t{}\t{}\t{:.6}\t",
+                        profile.len(),
+                        summary.min,
+                        summary.median,
+                        summary.below))
+        }
+        None => try!(write!(stream, "\t0\t0\t0\t0.000000\t")),
+    }
+    for (i, count) in profile.iter().enumerate() {
+        let separator = if i == 0 { "" } else { "," };
+        try!(write!(stream, "{}{}", separator, count));
+    }
+    writeln!(stream)
+}
+
+pub fn run(opts: Options) -> Result<()> {
+    match opts.alphabet {
+        alphabet::Kind::Dna => run_alphabet::<alphabet::Dna>(opts),
+        alphabet::Kind::Rna => run_alphabet::<alphabet::Rna>(opts),
+        alphabet::Kind::Protein => run_alphabet::<alphabet::Protein>(opts),
+    }
+}
+
+fn run_alphabet<A: Alphabet>(opts: Options) -> Result<()> {
+    let db = try!(database::open::<A>(&opts.database));
+    info!("Loaded {} k-mers from count database {}", db.counts.len(), opts.database);
+    let counts = db.counts;
+    let mut kmers = get_kmers::SliceKmers::new(db.kmer_len, get_kmers::Masking::All);
+    let stdout = io::stdout();
+    let mut stream = BufWriter::new(stdout.lock());
+    let mut profile = Vec::new();
+    try!(records::for_each_fragment(&opts.inputs, |fragment| {
+        profile.clear();
+        for record in fragment {
+            kmers.for_each(&record.seq, |kmer| profile.push(counts.count_of(kmer)));
+        }
+        write_profile(&mut stream, fragment, &profile, opts.threshold)
+            .chain_err(|| "Failed to write profile")
+    }));
+    stream.flush().chain_err(|| "Failed to write profile")
+}
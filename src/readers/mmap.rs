@@ -1,54 +1,50 @@
+use std::fs::File;
+
+use libc;
 use memmap;
 use memmap::Mmap;
 
 use errors::*;
 
-// Mmap is there simply for the Drop impl
-#[allow(dead_code)]
-pub struct Iter {
-    mmap: Mmap,
-    ptr: *const u8,
-    end: *const u8,
+/// A read-only memory map of a whole input file.
+/// Like any memory-mapped reader, this assumes the file isn't modified
+/// while it is being read.
+pub struct Map {
+    /// Empty files can't be mapped, so they have no map at all
+    mmap: Option<Mmap>,
 }
 
-impl Iter {
-    fn new(mmap: Mmap) -> Iter {
-        let ptr = mmap.ptr();
-        let len = mmap.len();
-        if len > ::std::isize::MAX as usize {
-            panic!("Tried to mmap file bigger than the maximum isize value");
-        }
-        let len = len as isize;
-        let end = unsafe { ptr.offset(len) };
-        Iter {
-            mmap: mmap,
-            ptr: ptr,
-            end: end,
+impl Map {
+    pub fn as_slice(&self) -> &[u8] {
+        match self.mmap {
+            Some(ref mmap) => unsafe { mmap.as_slice() },
+            None => &[],
         }
     }
 }
 
-impl Iterator for Iter {
-    type Item = u8;
-
-    fn next(&mut self) -> Option<u8> {
-        if self.ptr == self.end {
-            None
-        } else {
-            unsafe {
-                let old = self.ptr;
-                self.ptr = self.ptr.offset(1);
-                Some(*old)
-            }
-        }
+/// Tells the kernel the map will be read from start to end, so it reads ahead.
+/// This is only advice, so failing is harmless.
+#[cfg(unix)]
+fn advise_sequential(mmap: &Mmap) {
+    unsafe {
+        libc::madvise(mmap.ptr() as *mut libc::c_void,
+                      mmap.len(),
+                      libc::MADV_SEQUENTIAL);
     }
 }
 
-unsafe impl Sync for Iter {}
-unsafe impl Send for Iter {}
+#[cfg(not(unix))]
+fn advise_sequential(_: &Mmap) {}
 
-pub fn open(path: String) -> Result<Iter> {
-    let mmap = try!(Mmap::open_path(path, memmap::Protection::Read)
+pub fn open(path: String) -> Result<Map> {
+    let file = try!(File::open(path).chain_err(|| "Failed to open input file"));
+    let len = try!(file.metadata().chain_err(|| "Failed to open input file")).len();
+    if len == 0 {
+        return Ok(Map { mmap: None });
+    }
+    let mmap = try!(Mmap::open(&file, memmap::Protection::Read)
         .chain_err(|| "Failed to open input file as a memory map"));
-    Ok(Iter::new(mmap))
+    advise_sequential(&mmap);
+    Ok(Map { mmap: Some(mmap) })
 }
@@ -1,135 +1,549 @@
+use std::fs::File;
 use std::io;
-use std::io::Read;
-use std::sync::Mutex;
-use std::error::Error as ErrorTrait;
+use std::io::Write;
+use std::mem;
+use std::path::Path;
+use std::time::Instant;
 
 use jobsteal;
 
 use errors::*;
+use alphabet;
+use alphabet::Alphabet;
 use kmer_length::KmerLength;
-use error_string::ErrorString;
 use get_kmers;
 use output_counts;
 use kmer_tree;
-
-use readers;
+use kmer_counts;
+use kmer_counts::KmerCounts;
+use database;
+use jellyfish;
+use translate;
+use planner;
+use pipeline;
 use parsers;
+use dense;
+use sink::{Sink, MinCount, Histogram, Top};
+use stats;
+use stats::{Stats, Tally};
 
 /// The list of options for the runner
 pub struct Options {
     pub inputs: Vec<String>,
     pub stdin: bool,
-    pub kmer_len: KmerLength,
+    /// Every length is counted in the same pass over the inputs
+    pub kmer_lens: Vec<u8>,
+    /// Write the counts of each k-mer length to `<prefix>.k<length>` instead
+    /// of stdout, needed when there is more than one
+    pub output_prefix: Option<String>,
+    pub alphabet: alphabet::Kind,
     pub min_count: u16,
     pub only_presence: bool,
     pub threads: usize,
     pub mmap: bool,
+    pub input_format: parsers::Format,
     pub join_methods: Vec<kmer_tree::JoinMethod>,
+    /// Ignore `join_methods` and plan them from the counted inputs instead
+    pub auto_join: bool,
+    /// Skip sorting and deduplicating the output of the join methods
+    pub unordered: bool,
+    pub update: Option<String>,
+    pub jellyfish_imports: Vec<String>,
+    pub format: output_counts::Format,
+    /// Output a histogram of the counts instead of the counts themselves
+    pub histogram: bool,
+    /// Output only this many of the most frequent k-mers
+    pub top: Option<usize>,
+    /// Show the canonical form of each of the top k-mers
+    pub top_canonical: bool,
+    pub translate: Option<translate::GeneticCode>,
+    pub masking: get_kmers::Masking,
+    pub counting: Counting,
+    /// Tree counting flushes a leaf once a counting thread has buffered this
+    /// many k-mers, 0 makes a leaf for every section
+    pub flush_threshold: usize,
+    /// Also output k-mers which were never seen, only possible with dense counting
+    pub zero_counts: bool,
+    /// Write a summary of the run to this file
+    pub stats: Option<String>,
+    pub stats_format: stats::Format,
+}
+
+/// How k-mers are counted
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum Counting {
+    /// Dense when the table would be small, otherwise a tree
+    Auto,
+    /// A count per possible k-mer, see the `dense` module
+    Dense,
+    /// Lists of k-mers joined together, see the `kmer_tree` module
+    Tree,
+}
+
+fn leaf(counts: KmerCounts) -> kmer_tree::Node {
+    kmer_tree::Node::Leaf(kmer_tree::Leaf {
+        counts: counts,
+        sorted: false,
+    })
+}
+
+/// What a counting thread keeps for one k-mer length
+enum Counter {
+    Dense(Vec<u16>),
+    /// Leaves, and the k-mers not yet flushed into one
+    Tree(Vec<kmer_tree::Node>, KmerCounts),
+}
+
+impl Counter {
+    #[inline]
+    fn add(&mut self, kmer: u64) {
+        match *self {
+            Counter::Dense(ref mut table) => dense::increment(table, kmer),
+            Counter::Tree(_, ref mut buffer) => buffer.push(kmer, 1),
+        }
+    }
+
+    /// Turns the buffered k-mers into a leaf once there are at least `threshold`
+    fn flush(&mut self, threshold: usize) {
+        if let Counter::Tree(ref mut leaves, ref mut buffer) = *self {
+            if buffer.len() > 0 && buffer.len() >= threshold {
+                let counts = mem::replace(buffer, KmerCounts::new());
+                leaves.push(leaf(counts));
+            }
+        }
+    }
+}
+
+/// A counting thread's state
+struct ThreadState<A> {
+    kmers: get_kmers::SliceKmers<A>,
+    scanned: get_kmers::Scanned,
+    /// One per k-mer length
+    counters: Vec<Counter>,
+}
+
+/// The counts once every input has been read
+enum Counted {
+    Tree(Vec<kmer_tree::Node>),
+    Dense(Vec<u16>),
+}
+
+/// How many DNA bases, and other characters, a section to translate has
+fn scanned_bases(section: &[u8]) -> get_kmers::Scanned {
+    let mut scanned = get_kmers::Scanned::default();
+    for &c in section.iter().filter(|c| !c.is_ascii_whitespace()) {
+        if alphabet::Dna::encode(c).is_some() {
+            scanned.bases += 1;
+        } else {
+            scanned.invalid += 1;
+        }
+    }
+    scanned
+}
+
+/// Calls `f` with the index of the length and every k-mer of each length
+/// of a section, translating it first if requested.
+/// Returns how many bases the (untranslated) section had.
+fn for_each_kmer<A, F>(section: &[u8],
+                       kmers: &mut get_kmers::SliceKmers<A>,
+                       lengths: &[KmerLength<A>],
+                       translate: Option<translate::GeneticCode>,
+                       mut f: F)
+                       -> get_kmers::Scanned
+    where A: Alphabet,
+          F: FnMut(usize, u64)
+{
+    let code = match translate {
+        None => return kmers.for_each_length(section, lengths, 0, f),
+        Some(code) => code,
+    };
+    let seq = section.iter().cloned().filter(|c| !c.is_ascii_whitespace()).collect::<Vec<_>>();
+    for peptide in translate::six_frames(seq.as_slice(), code) {
+        kmers.for_each_length(peptide.as_slice(), lengths, 0, &mut f);
+    }
+    scanned_bases(&seq)
+}
+
+/// Like `for_each_kmer`, for a piece of a split record whose first `skip`
+/// symbols overlap the last piece. The k-mers ending within them were
+/// counted with that piece, and their bases are left out of the result.
+fn for_each_split_kmer<A, F>(section: &[u8],
+                             skip: usize,
+                             overlap: &pipeline::Overlap,
+                             kmers: &mut get_kmers::SliceKmers<A>,
+                             lengths: &[KmerLength<A>],
+                             translate: Option<translate::GeneticCode>,
+                             mut f: F)
+                             -> get_kmers::Scanned
+    where A: Alphabet,
+          F: FnMut(usize, u64)
+{
+    let rest = overlap.trim(section, skip, 0);
+    if translate.is_none() {
+        let prefix = kmers.for_each_length(&section[..section.len() - rest.len()], &[], 0, |_, _| {});
+        let scanned = kmers.for_each_length(section, lengths, skip, f);
+        return get_kmers::Scanned {
+            bases: scanned.bases - prefix.bases,
+            invalid: scanned.invalid - prefix.invalid,
+        };
+    }
+    // Peptides don't keep the positions of their bases, so each length is
+    // counted from the piece trimmed down to the overlap it needs
+    for (i, length) in lengths.iter().enumerate() {
+        let piece = overlap.trim(section, skip, 3 * length.length() as usize - 1);
+        for_each_kmer(piece, kmers, &[*length], translate, |_, kmer| f(i, kmer));
+    }
+    scanned_bases(rest)
 }
 
 pub fn run(opts: Options) -> Result<()> {
+    match opts.alphabet {
+        alphabet::Kind::Dna => run_alphabet::<alphabet::Dna>(opts),
+        alphabet::Kind::Rna => run_alphabet::<alphabet::Rna>(opts),
+        alphabet::Kind::Protein => run_alphabet::<alphabet::Protein>(opts),
+    }
+}
+
+fn run_alphabet<A: Alphabet>(opts: Options) -> Result<()> {
     let Options {
         inputs,
         stdin,
-        kmer_len,
+        kmer_lens,
+        output_prefix,
+        alphabet: _,
         min_count,
         only_presence,
         threads,
         mmap,
+        input_format,
         join_methods,
+        auto_join,
+        unordered,
+        update,
+        jellyfish_imports,
+        format,
+        histogram,
+        top,
+        top_canonical,
+        translate,
+        masking,
+        counting,
+        flush_threshold,
+        zero_counts,
+        stats,
+        stats_format,
     } = opts;
+    if translate.is_some() && A::NAME != alphabet::Protein::NAME {
+        bail!("Translated k-mers can only be counted with the protein alphabet");
+    }
+    if top_canonical && !A::COMPLEMENTED {
+        bail!("Only nucleotide k-mers have reverse complements");
+    }
+    if kmer_lens.len() > 1 {
+        if output_prefix.is_none() {
+            bail!("Counting several k-mer lengths needs an output prefix");
+        }
+        if update.is_some() || !jellyfish_imports.is_empty() || stats.is_some() {
+            bail!("Count databases, Jellyfish imports and stats only support one k-mer length");
+        }
+    }
+    let kmer_lens = kmer_lens.into_iter().map(KmerLength::<A>::new).collect::<Vec<_>>();
+    let longest = *kmer_lens.iter().max_by_key(|kmer_len| kmer_len.length()).unwrap();
     let mut job_pool = jobsteal::make_pool(threads).unwrap();
 
-    let stdin_handle = io::stdin();
+    let mut input_names = inputs.clone();
+    if stdin {
+        input_names.push("-".to_string());
+    }
 
     let mut inputs = try!(inputs.into_iter()
-        .map(|input| {
-            if mmap {
-                readers::mmap::open(input).map(|it| {
-                        Box::new(it.map(Ok)) as Box<Iterator<Item = Result<u8>> + Send + Sync>
+        .map(|input| pipeline::open(input, mmap))
+        .collect::<Result<Vec<_>>>());
+    if stdin {
+        inputs.push(pipeline::Input::Stream(Box::new(io::stdin())));
+    }
+
+    // Each length is counted densely or in a tree on its own
+    let tables = try!(kmer_lens.iter()
+        .map(|kmer_len| {
+            let bits = A::BITS as u32 * kmer_len.length() as u32;
+            let dense = match counting {
+                Counting::Auto => bits <= dense::AUTO_MAX_BITS,
+                Counting::Dense => {
+                    if bits > dense::MAX_BITS {
+                        bail!("Dense counting needs k-mers of at most {} bits, but these have {}",
+                              dense::MAX_BITS,
+                              bits);
+                    }
+                    true
+                }
+                Counting::Tree => false,
+            };
+            Ok(if dense { Some(dense::Tables::new(bits)) } else { None })
+        })
+        .collect::<Result<Vec<_>>>());
+    if zero_counts && tables.iter().any(|tables| tables.is_none()) {
+        bail!("K-mers with a count of zero can only be output with dense counting");
+    }
+
+    // Like the pool, which also runs jobs on its own thread, counting gets a
+    // thread more than asked for. Parsing is much cheaper than counting.
+    let counters = threads + 1;
+    let parsers = 1 + threads / 4;
+    let count_started = Instant::now();
+    let states = (0..counters)
+        .map(|_| {
+            ThreadState {
+                kmers: get_kmers::SliceKmers::new(longest, masking),
+                scanned: get_kmers::Scanned::default(),
+                counters: tables.iter()
+                    .map(|tables| match *tables {
+                        Some(ref tables) => Counter::Dense(tables.take()),
+                        None => Counter::Tree(Vec::new(), KmerCounts::new()),
                     })
+                    .collect(),
+            }
+        })
+        .collect();
+    let overlap = if translate.is_some() {
+        // A translated k-mer spans three bases per amino acid, and codons
+        // with invalid bases still take up their place in the frame
+        pipeline::Overlap {
+            symbols: 3 * longest.length() as usize - 1,
+            is_symbol: |c| !c.is_ascii_whitespace(),
+        }
+    } else {
+        pipeline::Overlap::kmers(longest)
+    };
+    let (states, summary) = try!(pipeline::run(inputs,
+                                               input_format,
+                                               overlap,
+                                               parsers,
+                                               states,
+                                               |state, record, skip| {
+            let ThreadState { ref mut kmers, ref mut scanned, ref mut counters } = *state;
+            let record_scanned = if skip > 0 {
+                for_each_split_kmer(record, skip, &overlap, kmers, &kmer_lens, translate, |i, kmer| {
+                    counters[i].add(kmer)
+                })
             } else {
-                readers::file::open(input)
-                    .map(|it| Box::new(it) as Box<Iterator<Item = Result<u8>> + Send + Sync>)
+                match *counters.as_mut_slice() {
+                    // A single counter is picked once per record instead of per k-mer
+                    [Counter::Dense(ref mut table)] => {
+                        for_each_kmer(record, kmers, &kmer_lens, translate, |_, kmer| {
+                            dense::increment(table, kmer)
+                        })
+                    }
+                    [Counter::Tree(_, ref mut buffer)] => {
+                        for_each_kmer(record, kmers, &kmer_lens, translate, |_, kmer| buffer.push(kmer, 1))
+                    }
+                    ref mut counters => {
+                        for_each_kmer(record, kmers, &kmer_lens, translate, |i, kmer| counters[i].add(kmer))
+                    }
+                }
+            };
+            scanned.add(record_scanned);
+            for counter in counters.iter_mut() {
+                counter.flush(flush_threshold);
             }
+            Ok(())
         })
-        .collect::<Result<Vec<_>>>());
+        .chain_err(|| "Encountered an error during k-mer counting"));
+    let counted_at = Instant::now();
 
-    if stdin {
-        inputs.push(Box::new(stdin_handle.bytes()
-                             .map(|r| r.chain_err(|| "Failed to read from stdin")))
-                    as Box<Iterator<Item = Result<u8>> + Send + Sync>);
-    }
-
-    // TODO: multiple parser types
-    let inputs = inputs.into_iter().map(parsers::multifasta::SectionReader::new);
-
-    let input_counts = Mutex::new(Ok(Vec::new()));
-    job_pool.scope(|scope| {
-        let input_counts_ref = &input_counts;
-        for mut input in inputs {
-            scope.submit(move || {
-                let mut section_counts = Ok(Vec::new());
-                while let Some(section) = input.next_section() {
-                    let kmers =
-                        section.and_then(|section| get_kmers::Kmers::new(section, kmer_len.clone()))
-                            .and_then(|kmer_iter| {
-                                kmer_iter.map(|r| r.map(|n| Some((n, 1))))
-                                    .collect::<Result<Vec<_>>>()
-                            })
-                            .map(|v| {
-                                kmer_tree::Node::Leaf(kmer_tree::Leaf {
-                                    counts: v,
-                                    sorted: false,
-                                })
-                            });
-                    match kmers {
-                        Err(e) => {
-                            section_counts = Err(e);
-                            break;
-                        }
-                        Ok(vec) => {
-                            let _ = section_counts.as_mut().map(|list| list.push(vec));
+    let mut scanned = get_kmers::Scanned::default();
+    let mut counted = tables.iter().map(|_| Vec::new()).collect::<Vec<_>>();
+    for state in states {
+        scanned.add(state.scanned);
+        for (i, mut counter) in state.counters.into_iter().enumerate() {
+            counter.flush(0);
+            counted[i].push(counter);
+        }
+    }
+    let counted = counted.into_iter()
+        .zip(tables)
+        .map(|(counters, tables)| {
+            match tables {
+                Some(tables) => {
+                    for counter in counters {
+                        if let Counter::Dense(table) = counter {
+                            tables.put_back(table);
                         }
                     }
+                    let table = tables.merge(&job_pool.spawner());
+                    info!("Done counting k-mers into a dense table of {} entries", table.len());
+                    Counted::Dense(table)
                 }
-                let section_counts = section_counts.map(kmer_tree::Node::Branch);
-                let mut input_counts = input_counts_ref.lock().unwrap();
-                match section_counts {
-                    Err(e) => *input_counts = Err(e),
-                    Ok(vec) => {
-                        let _ = input_counts.as_mut().map(|list| list.push(vec));
-                    }
+                None => {
+                    let counts = counters.into_iter()
+                        .filter_map(|counter| match counter {
+                            Counter::Tree(leaves, _) => Some(kmer_tree::Node::Branch(leaves)),
+                            Counter::Dense(_) => None,
+                        })
+                        .collect::<Vec<_>>();
+                    info!("Done counting k-mers into {} leaves",
+                          counts.iter()
+                              .map(|node| match *node {
+                                  kmer_tree::Node::Branch(ref leaves) => leaves.len(),
+                                  kmer_tree::Node::Leaf(_) => 1,
+                              })
+                              .sum::<usize>());
+                    Counted::Tree(counts)
                 }
-            });
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let records = input_names.iter().cloned().zip(summary.records).collect::<Vec<_>>();
+
+    for (kmer_len, mut counted) in kmer_lens.into_iter().zip(counted) {
+        let mut input_names = input_names.clone();
+        for path in jellyfish_imports.iter() {
+            let imported = try!(jellyfish::open_dump(path, kmer_len));
+            info!("Imported {} k-mers from Jellyfish dump {}", imported.len(), path);
+            match counted {
+                Counted::Tree(ref mut counts) => {
+                    counts.push(leaf(imported))
+                }
+                Counted::Dense(ref mut table) => dense::add_counts(table, &imported),
+            }
+            input_names.push(path.clone());
+        }
+
+        let mut database = None;
+        if let Some(ref path) = update {
+            if Path::new(path).exists() {
+                let existing = try!(database::open::<A>(path));
+                if existing.kmer_len.length() != kmer_len.length() {
+                    bail!("Count database {} uses a k-mer length of {}, not {}",
+                          path,
+                          existing.kmer_len.length(),
+                          kmer_len.length());
+                }
+                info!("Loaded {} k-mers from count database {}",
+                      existing.counts.len(),
+                      path);
+                database = Some(existing);
+            }
         }
-    });
-    let counts = try!(input_counts.into_inner()
-                      .map_err(|e| ErrorString::new(e.description().to_string())) // e is not Sync
-                      .chain_err(|| {
-                          "A k-mer counting thread panicked, poisoning the output mutex"
-                      }));
-    let counts = try!(counts.chain_err(|| "Encountered an error during k-mer counting"));
-    info!("Done counting {} k-mers", counts.len());
-
-    let mut all_counts = None;
-    let join_methods = join_methods.as_slice();
-    job_pool.scope(|scope| {
-        if only_presence {
-            all_counts = Some(kmer_tree::Node::Branch(counts)
-                .consolidate(scope, join_methods, &|_, _, _| {})
-                .counts);
+
+        // Only opened when the counts aren't going to a database
+        let open_stream = || -> Result<Box<Write>> {
+            Ok(match output_prefix {
+                Some(ref prefix) => {
+                    let path = format!("{}.k{}", prefix, kmer_len.length());
+                    Box::new(try!(File::create(&path)
+                        .chain_err(|| format!("Failed to create output file {}", path))))
+                }
+                None => Box::new(io::stdout()),
+            })
+        };
+
+        // Every k-mer is written when zero counts are wanted
+        let min_count = if zero_counts { 0 } else { min_count };
+        let mut database_counts = None;
+        let (sink, min_count): (Box<Sink>, u16) = if let Some(ref path) = update {
+            // Every count is kept in the database, regardless of the minimum
+            let mut inputs = Vec::new();
+            if let Some(database) = database {
+                inputs = database.inputs;
+                database_counts = Some(database.counts);
+            }
+            inputs.extend(input_names);
+            (Box::new(try!(database::create(path, kmer_len, inputs.as_slice()))), 0)
+        } else if let Some(n) = top {
+            (Box::new(Top::new(try!(open_stream()), kmer_len, n, top_canonical)), min_count)
+        } else if histogram {
+            (Box::new(Histogram::new(try!(open_stream()))), min_count)
         } else {
-            all_counts = Some(kmer_tree::Node::Branch(counts)
-                .consolidate(scope, join_methods, &|_, value, other| *value += other)
-                .counts);
+            let stream = try!(open_stream());
+            (Box::new(output_counts::TextSink::new(stream, kmer_len, format)), min_count)
         };
-    });
-    let counts = all_counts.unwrap();
-    info!("Done consolidating {} k-mers", counts.len());
+        // The tally only sees the k-mers which are written
+        let mut sink = MinCount::new(Tally::new(sink, counted_at), min_count);
+        // Only an explicit --unordered skips the final sort, where it isn't
+        // needed for the database or top list
+        let sorted_output = !unordered || update.is_some() || top.is_some();
+        // Without the final sort, a k-mer may be written once per leaf
+        let duplicates = match counted {
+            Counted::Tree(_) => !sorted_output,
+            Counted::Dense(_) => false,
+        };
+        let written = match counted {
+            Counted::Dense(mut table) => {
+                if let Some(ref existing) = database_counts {
+                    dense::add_counts(&mut table, existing);
+                }
+                try!(dense::write_all(&table, kmer_len, &mut sink, zero_counts, only_presence))
+            }
+            Counted::Tree(counts) => {
+                let mut join_methods = join_methods.clone();
+                let root = if let Some(existing) = database_counts {
+                    // The database is already sorted, so merging it in with a sort join
+                    // only needs the new counts to be sorted.
+                    join_methods.insert(0, kmer_tree::JoinMethod::Sort);
+                    let existing = kmer_tree::Leaf {
+                        counts: existing,
+                        sorted: true,
+                    };
+                    kmer_tree::Node::Branch(vec![kmer_tree::Node::Branch(counts),
+                                                 kmer_tree::Node::Leaf(existing)])
+                } else if sorted_output {
+                    // The output is sorted and deduplicated by a sort join above
+                    // the requested join methods. Leaves they already sorted
+                    // aren't sorted again.
+                    join_methods.insert(0, kmer_tree::JoinMethod::Sort);
+                    kmer_tree::Node::Branch(vec![kmer_tree::Node::Branch(counts)])
+                } else {
+                    kmer_tree::Node::Branch(counts)
+                };
+                if auto_join {
+                    // The pool's own thread also runs jobs
+                    join_methods = planner::plan(&root, threads + 1, sorted_output);
+                }
 
-    let stdout = io::stdout();
-    output_counts::output(stdout.lock(), counts, kmer_len, min_count);
-    info!("Done!");
+                let mut written = Ok(0);
+                let join_methods = join_methods.as_slice();
+                job_pool.scope(|scope| {
+                    let sink = &mut sink;
+                    written = if only_presence {
+                        root.consolidate_into(scope, join_methods, &|_, _, _| {}, sink)
+                    } else {
+                        root.consolidate_into(scope, join_methods, &kmer_counts::add_counts, sink)
+                    };
+                });
+                try!(written)
+            }
+        };
+        try!(sink.finish());
+        info!("Done consolidating {} k-mers of length {}", written, kmer_len.length());
+
+        if let Some(ref path) = stats {
+            let tally = sink.inner();
+            // Presence counts are all 1, and duplicates split the counts of a k-mer
+            let exact_counts = !only_presence && !duplicates;
+            let stats = Stats {
+                records: records.clone(),
+                bases: scanned.bases,
+                invalid: scanned.invalid,
+                kmers: if only_presence { None } else { Some(tally.kmers) },
+                distinct: if duplicates { None } else { Some(tally.distinct) },
+                singletons: if exact_counts { Some(tally.singletons) } else { None },
+                max_count: if exact_counts { Some(tally.max_count) } else { None },
+                read_time: summary.read_time,
+                count_time: counted_at - count_started,
+                consolidate_time: tally.consolidate_time(),
+                output_time: tally.output_time(),
+            };
+            try!(stats.save(path, stats_format));
+            info!("Saved run statistics to {}", path);
+        }
+    }
+
+    if let Some(path) = update {
+        info!("Done! Saved count database {}", path);
+    } else {
+        info!("Done!");
+    }
     Ok(())
 }
@@ -0,0 +1,188 @@
+//! Vectorized encoding of 2-bit nucleotides. A, C, G, T and U are encoded by
+//! `((c >> 1) ^ (c >> 2)) & 3` in either case, so 32 bytes of text can be
+//! encoded at once, and checked against the valid letters with compares.
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+/// Bytes encoded by each call to `pack`
+pub const CHUNK: usize = 32;
+
+/// Which instructions `pack` uses, picked at runtime
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Level {
+    Scalar,
+    Sse2,
+    Avx2,
+}
+
+impl Level {
+    #[cfg(target_arch = "x86_64")]
+    pub fn detect() -> Level {
+        if is_x86_feature_detected!("avx2") {
+            Level::Avx2
+        } else if is_x86_feature_detected!("sse2") {
+            Level::Sse2
+        } else {
+            Level::Scalar
+        }
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    pub fn detect() -> Level {
+        Level::Scalar
+    }
+
+    /// Whether this CPU has the instructions of the level
+    #[cfg(target_arch = "x86_64")]
+    pub fn supported(self) -> bool {
+        match self {
+            Level::Scalar => true,
+            Level::Sse2 => is_x86_feature_detected!("sse2"),
+            Level::Avx2 => is_x86_feature_detected!("avx2"),
+        }
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    pub fn supported(self) -> bool {
+        self == Level::Scalar
+    }
+}
+
+/// The 2-bit codes of a chunk, split into a bit per byte of the chunk
+pub struct Packed {
+    /// Set for bytes which are valid letters, the other codes are garbage
+    pub valid: u32,
+    pub low: u32,
+    pub high: u32,
+}
+
+impl Packed {
+    #[inline]
+    pub fn code(&self, i: usize) -> u8 {
+        (((self.high >> i) & 1) << 1 | ((self.low >> i) & 1)) as u8
+    }
+}
+
+#[inline]
+fn code(c: u8) -> u8 {
+    ((c >> 1) ^ (c >> 2)) & 3
+}
+
+/// The bytes which are valid letters, after or-ing with `fold` (0x20 to
+/// ignore case)
+#[derive(Clone, Copy)]
+pub struct Letters {
+    fold: u8,
+    letters: [u8; 8],
+    count: usize,
+}
+
+impl Letters {
+    /// Finds the letters of an encoding table, where codes of 4 and up aren't
+    /// letters. Returns `None` unless the table matches the vectorized encoding.
+    pub fn from_table(table: &[u8; 256]) -> Option<Letters> {
+        let valid = (0..256).map(|c| c as u8).filter(|&c| table[c as usize] < 4).collect::<Vec<_>>();
+        if valid.is_empty() || valid.iter().any(|&c| code(c) != table[c as usize]) {
+            return None;
+        }
+        let case_folds = valid.iter()
+            .all(|&c| c.is_ascii_alphabetic() && table[(c ^ 0x20) as usize] < 4);
+        let fold = if case_folds { 0x20 } else { 0 };
+        let mut folded = valid.iter().map(|&c| c | fold).collect::<Vec<_>>();
+        folded.sort();
+        folded.dedup();
+        if folded.len() > 8 {
+            return None;
+        }
+        let mut letters = [folded[0]; 8];
+        letters[..folded.len()].copy_from_slice(&folded);
+        Some(Letters {
+            fold: fold,
+            letters: letters,
+            count: folded.len(),
+        })
+    }
+
+    #[inline]
+    fn contains(&self, c: u8) -> bool {
+        self.letters[..self.count].contains(&(c | self.fold))
+    }
+}
+
+/// Encodes `CHUNK` bytes of text
+///
+/// # Safety
+///
+/// The level must be supported by the CPU, see `Level::supported`.
+#[inline]
+pub unsafe fn pack(level: Level, letters: &Letters, chunk: &[u8]) -> Packed {
+    assert!(chunk.len() >= CHUNK);
+    match level {
+        #[cfg(target_arch = "x86_64")]
+        Level::Avx2 => pack_avx2(letters, chunk),
+        #[cfg(target_arch = "x86_64")]
+        Level::Sse2 => pack_sse2(letters, chunk),
+        _ => pack_scalar(letters, chunk),
+    }
+}
+
+pub fn pack_scalar(letters: &Letters, chunk: &[u8]) -> Packed {
+    let mut packed = Packed {
+        valid: 0,
+        low: 0,
+        high: 0,
+    };
+    for (i, &c) in chunk[..CHUNK].iter().enumerate() {
+        let n = code(c) as u32;
+        packed.valid |= (letters.contains(c) as u32) << i;
+        packed.low |= (n & 1) << i;
+        packed.high |= (n >> 1) << i;
+    }
+    packed
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn pack_sse2(letters: &Letters, chunk: &[u8]) -> Packed {
+    let mut packed = Packed {
+        valid: 0,
+        low: 0,
+        high: 0,
+    };
+    for half in 0..2 {
+        let text = _mm_loadu_si128(chunk.as_ptr().add(half * 16) as *const __m128i);
+        let folded = _mm_or_si128(text, _mm_set1_epi8(letters.fold as i8));
+        let mut valid = _mm_setzero_si128();
+        for &letter in &letters.letters[..letters.count] {
+            valid = _mm_or_si128(valid, _mm_cmpeq_epi8(folded, _mm_set1_epi8(letter as i8)));
+        }
+        // 16-bit shifts leak bits between bytes, but only above the code
+        let codes = _mm_and_si128(_mm_xor_si128(_mm_srli_epi16(text, 1), _mm_srli_epi16(text, 2)),
+                                  _mm_set1_epi8(3));
+        let shift = half * 16;
+        packed.valid |= (_mm_movemask_epi8(valid) as u32 & 0xffff) << shift;
+        packed.low |= (_mm_movemask_epi8(_mm_slli_epi16(codes, 7)) as u32 & 0xffff) << shift;
+        packed.high |= (_mm_movemask_epi8(_mm_slli_epi16(codes, 6)) as u32 & 0xffff) << shift;
+    }
+    packed
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn pack_avx2(letters: &Letters, chunk: &[u8]) -> Packed {
+    let text = _mm256_loadu_si256(chunk.as_ptr() as *const __m256i);
+    let folded = _mm256_or_si256(text, _mm256_set1_epi8(letters.fold as i8));
+    let mut valid = _mm256_setzero_si256();
+    for &letter in &letters.letters[..letters.count] {
+        valid = _mm256_or_si256(valid, _mm256_cmpeq_epi8(folded, _mm256_set1_epi8(letter as i8)));
+    }
+    let codes = _mm256_and_si256(_mm256_xor_si256(_mm256_srli_epi16(text, 1),
+                                                  _mm256_srli_epi16(text, 2)),
+                                 _mm256_set1_epi8(3));
+    Packed {
+        valid: _mm256_movemask_epi8(valid) as u32,
+        low: _mm256_movemask_epi8(_mm256_slli_epi16(codes, 7)) as u32,
+        high: _mm256_movemask_epi8(_mm256_slli_epi16(codes, 6)) as u32,
+    }
+}
@@ -0,0 +1,159 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::Write;
+use std::io::BufWriter;
+
+use errors::*;
+use alphabet::Alphabet;
+use kmer_length::KmerLength;
+use output_counts;
+
+/// Receives the final k-mer counts as they are produced
+pub trait Sink {
+    fn write(&mut self, kmer: u64, count: u16) -> Result<()>;
+
+    /// Called once after the last k-mer has been written
+    fn finish(&mut self) -> Result<()>;
+}
+
+impl<S: Sink + ?Sized> Sink for Box<S> {
+    #[inline]
+    fn write(&mut self, kmer: u64, count: u16) -> Result<()> {
+        (**self).write(kmer, count)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        (**self).finish()
+    }
+}
+
+/// Drops k-mers below a minimum count before they reach the inner sink
+pub struct MinCount<S> {
+    inner: S,
+    min_count: u16,
+}
+
+impl<S: Sink> MinCount<S> {
+    pub fn new(inner: S, min_count: u16) -> MinCount<S> {
+        MinCount {
+            inner: inner,
+            min_count: min_count,
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S: Sink> Sink for MinCount<S> {
+    #[inline]
+    fn write(&mut self, kmer: u64, count: u16) -> Result<()> {
+        if count < self.min_count {
+            return Ok(());
+        }
+        self.inner.write(kmer, count)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.inner.finish()
+    }
+}
+
+/// Counts how many k-mers have each count, written like `jellyfish histo`
+pub struct Histogram<W: Write> {
+    stream: BufWriter<W>,
+    histogram: Vec<u64>,
+}
+
+impl<W: Write> Histogram<W> {
+    pub fn new(stream: W) -> Histogram<W> {
+        Histogram {
+            stream: BufWriter::new(stream),
+            histogram: vec![0; ::std::u16::MAX as usize + 1],
+        }
+    }
+}
+
+impl<W: Write> Sink for Histogram<W> {
+    #[inline]
+    fn write(&mut self, _: u64, count: u16) -> Result<()> {
+        self.histogram[count as usize] += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        for (count, &kmers) in self.histogram.iter().enumerate() {
+            if kmers > 0 {
+                try!(writeln!(self.stream, "{} {}", count, kmers)
+                    .chain_err(|| "Failed to write histogram to output stream"));
+            }
+        }
+        self.stream.flush().chain_err(|| "Failed to write histogram to output stream")
+    }
+}
+
+/// Keeps the `n` most frequent k-mers in a bounded heap, then writes them
+/// with their share of every counted k-mer
+pub struct Top<W: Write, A> {
+    stream: BufWriter<W>,
+    kmer_len: KmerLength<A>,
+    n: usize,
+    /// Also write the lesser of each k-mer and its reverse complement
+    canonical: bool,
+    /// The least frequent kept k-mer is on top, ties keep the lesser k-mer
+    heap: BinaryHeap<Reverse<(u16, Reverse<u64>)>>,
+    total: u64,
+}
+
+impl<W: Write, A: Alphabet> Top<W, A> {
+    pub fn new(stream: W, kmer_len: KmerLength<A>, n: usize, canonical: bool) -> Top<W, A> {
+        Top {
+            stream: BufWriter::new(stream),
+            kmer_len: kmer_len,
+            n: n,
+            canonical: canonical,
+            heap: BinaryHeap::with_capacity(n + 1),
+            total: 0,
+        }
+    }
+}
+
+impl<W: Write, A: Alphabet> Sink for Top<W, A> {
+    #[inline]
+    fn write(&mut self, kmer: u64, count: u16) -> Result<()> {
+        self.total += count as u64;
+        let entry = Reverse((count, Reverse(kmer)));
+        if self.heap.len() < self.n {
+            self.heap.push(entry);
+        } else if self.heap.peek().is_some_and(|least| entry < *least) {
+            self.heap.pop();
+            self.heap.push(entry);
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let top = ::std::mem::take(&mut self.heap).into_sorted_vec();
+        for Reverse((count, Reverse(kmer))) in top {
+            let share = if self.total == 0 {
+                0.0
+            } else {
+                count as f64 / self.total as f64
+            };
+            let stream = &mut self.stream;
+            let kmer_text = output_counts::kmer_text(kmer, self.kmer_len);
+            try!(stream.write_all(&kmer_text)
+                .and_then(|_| write!(stream, "\t{}\t{:.4e}", count, share))
+                .chain_err(|| "Failed to write top k-mers to output stream"));
+            if self.canonical {
+                let canonical = output_counts::kmer_text(self.kmer_len.canonical(kmer), self.kmer_len);
+                try!(stream.write_all(b"\t")
+                    .and_then(|_| stream.write_all(&canonical))
+                    .chain_err(|| "Failed to write top k-mers to output stream"));
+            }
+            try!(stream.write_all(b"\n").chain_err(|| "Failed to write top k-mers to output stream"));
+        }
+        self.stream.flush().chain_err(|| "Failed to write top k-mers to output stream")
+    }
+}
@@ -0,0 +1,362 @@
+//! MinHash sketches of the k-mers of each input, and estimates of how similar
+//! two inputs are from their sketches alone.
+//!
+//! K-mers are hashed as their packed `u64`, so sketches are only comparable
+//! with other sketches made by this program with the same k-mer length,
+//! alphabet and strandedness.
+
+use std::collections::{BTreeSet, HashSet};
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::io::BufReader;
+use std::io::BufWriter;
+
+use errors::*;
+use alphabet;
+use alphabet::Alphabet;
+use kmer_length::KmerLength;
+use get_kmers;
+use pipeline;
+use parsers;
+use matrix;
+
+/// Identifies a sketch file, the trailing digit is the format version
+const MAGIC: &[u8; 8] = b"KMERSKT1";
+
+/// Which hashes a sketch keeps
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Mode {
+    /// The given number of smallest hashes (classic MinHash, as in Mash)
+    Bottom(usize),
+    /// Every hash below `u64::MAX / scaled` (FracMinHash), so the sketch
+    /// grows with the number of distinct k-mers
+    Scaled(u64),
+}
+
+/// Mixes a k-mer into a well distributed hash (the splitmix64 finalizer).
+/// It is a bijection, so distinct k-mers never collide.
+#[inline]
+pub fn hash(kmer: u64) -> u64 {
+    let mut z = kmer.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Collects the hashes a sketch keeps
+pub enum Builder {
+    Bottom(usize, BTreeSet<u64>),
+    Scaled(u64, HashSet<u64>),
+}
+
+impl Builder {
+    pub fn new(mode: Mode) -> Builder {
+        match mode {
+            Mode::Bottom(size) => Builder::Bottom(size, BTreeSet::new()),
+            Mode::Scaled(scaled) => Builder::Scaled(::std::u64::MAX / scaled, HashSet::new()),
+        }
+    }
+
+    #[inline]
+    pub fn add(&mut self, hash: u64) {
+        match *self {
+            Builder::Bottom(size, ref mut hashes) => {
+                if hashes.len() < size {
+                    hashes.insert(hash);
+                } else if hashes.iter().next_back().is_some_and(|&max| hash < max) &&
+                          hashes.insert(hash) {
+                    let max = *hashes.iter().next_back().unwrap();
+                    hashes.remove(&max);
+                }
+            }
+            Builder::Scaled(max, ref mut hashes) => {
+                if hash < max {
+                    hashes.insert(hash);
+                }
+            }
+        }
+    }
+
+    /// Adds the hashes of another builder, as if they were added to this one
+    pub fn merge(&mut self, other: Builder) {
+        for hash in other.into_hashes() {
+            self.add(hash);
+        }
+    }
+
+    /// The kept hashes, sorted
+    pub fn into_hashes(self) -> Vec<u64> {
+        match self {
+            Builder::Bottom(_, hashes) => hashes.into_iter().collect(),
+            Builder::Scaled(_, hashes) => {
+                let mut hashes = hashes.into_iter().collect::<Vec<_>>();
+                hashes.sort();
+                hashes
+            }
+        }
+    }
+}
+
+/// The sketch of one input
+#[derive(PartialEq, Eq, Debug)]
+pub struct Sketch {
+    pub name: String,
+    pub alphabet: String,
+    pub kmer_len: u8,
+    /// Whether each k-mer was hashed as the lesser of it and its reverse complement
+    pub canonical: bool,
+    pub mode: Mode,
+    /// Sorted and distinct
+    pub hashes: Vec<u64>,
+}
+
+impl Sketch {
+    /// Every hash of the input at or below this one is in the sketch
+    fn max_hash(&self) -> u64 {
+        match self.mode {
+            Mode::Bottom(size) if self.hashes.len() >= size => {
+                self.hashes.last().cloned().unwrap_or(::std::u64::MAX)
+            }
+            Mode::Bottom(_) => ::std::u64::MAX,
+            Mode::Scaled(scaled) => ::std::u64::MAX / scaled - 1,
+        }
+    }
+}
+
+/// Similarity estimates between two sketches
+#[derive(Debug)]
+pub struct Comparison {
+    pub jaccard: f64,
+    /// The fraction of the first input's k-mers which are in the second
+    pub containment: f64,
+    /// The fraction of the second input's k-mers which are in the first
+    pub containment_other: f64,
+    /// The Mash distance, an estimate of the mutation rate between the inputs
+    pub distance: f64,
+}
+
+/// The mutation rate at which two sequences are expected to share a Jaccard
+/// index of `jaccard` between their k-mers, capped at 1
+pub fn mash_distance(jaccard: f64, kmer_len: u8) -> f64 {
+    if jaccard <= 0.0 {
+        return 1.0;
+    } else if jaccard >= 1.0 {
+        return 0.0;
+    }
+    let distance = -(2.0 * jaccard / (1.0 + jaccard)).ln() / kmer_len as f64;
+    distance.clamp(0.0, 1.0)
+}
+
+/// `n / d`, or 0 if `d` is
+pub fn ratio(n: usize, d: usize) -> f64 {
+    if d == 0 { 0.0 } else { n as f64 / d as f64 }
+}
+
+/// Compares two sketches over the hashes both of them are complete up to,
+/// which are a uniform sample of the k-mers of both inputs. Sketches of
+/// different modes can be compared this way, but are less accurate.
+pub fn compare(a: &Sketch, b: &Sketch) -> Result<Comparison> {
+    if a.alphabet != b.alphabet || a.kmer_len != b.kmer_len || a.canonical != b.canonical {
+        bail!("Sketches {} and {} have different alphabets, k-mer lengths or strandedness",
+              a.name,
+              b.name);
+    }
+    let max = ::std::cmp::min(a.max_hash(), b.max_hash());
+    let a_hashes = &a.hashes[..a.hashes.iter().take_while(|&&h| h <= max).count()];
+    let b_hashes = &b.hashes[..b.hashes.iter().take_while(|&&h| h <= max).count()];
+    let shared = matrix::shared(a_hashes, b_hashes);
+    let jaccard = ratio(shared, a_hashes.len() + b_hashes.len() - shared);
+    Ok(Comparison {
+        jaccard: jaccard,
+        containment: ratio(shared, a_hashes.len()),
+        containment_other: ratio(shared, b_hashes.len()),
+        distance: mash_distance(jaccard, a.kmer_len),
+    })
+}
+
+fn read_exact<R: Read>(reader: &mut R, len: usize) -> Result<Vec<u8>> {
+    let mut buf = vec![0; len];
+    try!(reader.read_exact(buf.as_mut_slice()).chain_err(|| "Unexpected end of sketch file"));
+    Ok(buf)
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut buf = [0; 8];
+    try!(reader.read_exact(&mut buf).chain_err(|| "Unexpected end of sketch file"));
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_string<R: Read>(reader: &mut R) -> Result<String> {
+    let len = try!(read_u64(reader)) as usize;
+    let bytes = try!(read_exact(reader, len));
+    String::from_utf8(bytes).chain_err(|| "Sketch file has a name which is not valid UTF-8")
+}
+
+fn write_string<W: Write>(stream: &mut W, s: &str) -> io::Result<()> {
+    try!(stream.write_all(&(s.len() as u64).to_le_bytes()));
+    stream.write_all(s.as_bytes())
+}
+
+/// Reads every sketch of a sketch file
+pub fn read<R: Read>(mut reader: R) -> Result<Vec<Sketch>> {
+    let magic = try!(read_exact(&mut reader, MAGIC.len()));
+    if magic.as_slice() != &MAGIC[..] {
+        bail!("Input is not a sketch file (bad magic number)");
+    }
+    let count = try!(read_u64(&mut reader));
+    let mut sketches = Vec::new();
+    for _ in 0..count {
+        let name = try!(read_string(&mut reader));
+        let alphabet = try!(read_string(&mut reader));
+        let header = try!(read_exact(&mut reader, 3));
+        let param = try!(read_u64(&mut reader));
+        let mode = match header[2] {
+            0 => Mode::Bottom(param as usize),
+            1 if param > 0 => Mode::Scaled(param),
+            _ => bail!("Sketch {} has an invalid mode", name),
+        };
+        let len = try!(read_u64(&mut reader));
+        let mut hashes = Vec::new();
+        for _ in 0..len {
+            let hash = try!(read_u64(&mut reader));
+            if hashes.last().is_some_and(|&last| hash <= last) {
+                bail!("Sketch {} is not sorted", name);
+            }
+            hashes.push(hash);
+        }
+        sketches.push(Sketch {
+            name: name,
+            alphabet: alphabet,
+            kmer_len: header[0],
+            canonical: header[1] != 0,
+            mode: mode,
+            hashes: hashes,
+        });
+    }
+    Ok(sketches)
+}
+
+pub fn write<W: Write>(mut stream: W, sketches: &[Sketch]) -> io::Result<()> {
+    try!(stream.write_all(&MAGIC[..]));
+    try!(stream.write_all(&(sketches.len() as u64).to_le_bytes()));
+    for sketch in sketches {
+        try!(write_string(&mut stream, &sketch.name));
+        try!(write_string(&mut stream, &sketch.alphabet));
+        let (mode, param) = match sketch.mode {
+            Mode::Bottom(size) => (0, size as u64),
+            Mode::Scaled(scaled) => (1, scaled),
+        };
+        try!(stream.write_all(&[sketch.kmer_len, sketch.canonical as u8, mode]));
+        try!(stream.write_all(&param.to_le_bytes()));
+        try!(stream.write_all(&(sketch.hashes.len() as u64).to_le_bytes()));
+        for hash in sketch.hashes.iter() {
+            try!(stream.write_all(&hash.to_le_bytes()));
+        }
+    }
+    stream.flush()
+}
+
+pub fn open(path: &str) -> Result<Vec<Sketch>> {
+    let file = try!(File::open(path).chain_err(|| "Failed to open sketch file"));
+    read(BufReader::new(file)).chain_err(|| format!("Failed to read sketch file {}", path))
+}
+
+/// The options of the `sketch` subcommand
+pub struct Options {
+    pub inputs: Vec<String>,
+    pub output: String,
+    pub kmer_len: u8,
+    pub alphabet: alphabet::Kind,
+    pub mode: Mode,
+    /// Hash nucleotide k-mers as they are, instead of their canonical form
+    pub stranded: bool,
+    pub threads: usize,
+    pub mmap: bool,
+}
+
+/// Sketches every input separately and saves them to one file
+pub fn run(opts: Options) -> Result<()> {
+    match opts.alphabet {
+        alphabet::Kind::Dna => run_alphabet::<alphabet::Dna>(opts),
+        alphabet::Kind::Rna => run_alphabet::<alphabet::Rna>(opts),
+        alphabet::Kind::Protein => run_alphabet::<alphabet::Protein>(opts),
+    }
+}
+
+fn run_alphabet<A: Alphabet>(opts: Options) -> Result<()> {
+    let Options { inputs, output, kmer_len, alphabet: _, mode, stranded, threads, mmap } = opts;
+    let kmer_len = KmerLength::<A>::new(kmer_len);
+    let canonical = A::COMPLEMENTED && !stranded;
+    let counters = threads + 1;
+    let parsers = 1 + threads / 4;
+    let mut sketches = Vec::new();
+    for path in inputs {
+        let input = vec![try!(pipeline::open(path.clone(), mmap))];
+        let states = (0..counters)
+            .map(|_| {
+                (get_kmers::SliceKmers::new(kmer_len, get_kmers::Masking::All),
+                 Builder::new(mode))
+            })
+            .collect();
+        let (format, overlap) = (parsers::Format::Fasta, pipeline::Overlap::kmers(kmer_len));
+        let (states, _) = try!(pipeline::run(input, format, overlap, parsers, states, |state, record, _| {
+                let (ref mut kmers, ref mut builder) = *state;
+                let mut add = |kmer| builder.add(hash(kmer));
+                if canonical {
+                    kmers.for_each_canonical(record, &mut add);
+                } else {
+                    kmers.for_each(record, &mut add);
+                }
+                Ok(())
+            })
+            .chain_err(|| format!("Failed to sketch {}", path)));
+        let mut states = states.into_iter().map(|(_, builder)| builder);
+        let mut builder = states.next().unwrap();
+        for other in states {
+            builder.merge(other);
+        }
+        let hashes = builder.into_hashes();
+        info!("Sketched {} into {} hashes", path, hashes.len());
+        sketches.push(Sketch {
+            name: path,
+            alphabet: A::NAME.to_string(),
+            kmer_len: kmer_len.length(),
+            canonical: canonical,
+            mode: mode,
+            hashes: hashes,
+        });
+    }
+    let file = try!(File::create(&output).chain_err(|| "Failed to create sketch file"));
+    try!(write(BufWriter::new(file), &sketches).chain_err(|| "Failed to write sketch file"));
+    info!("Done! Saved {} sketches to {}", sketches.len(), output);
+    Ok(())
+}
+
+/// Compares every pair of sketches of the given files, writing a line per pair
+pub fn run_compare(paths: &[String]) -> Result<()> {
+    let mut sketches = Vec::new();
+    for path in paths {
+        sketches.extend(try!(open(path)));
+    }
+    let stdout = io::stdout();
+    let mut stream = BufWriter::new(stdout.lock());
+    try!(writeln!(stream, "#a\tb\tjaccard\tcontainment_a_in_b\tcontainment_b_in_a\tmash_distance")
+        .chain_err(|| "Failed to write comparison"));
+    for (i, a) in sketches.iter().enumerate() {
+        for b in sketches[i + 1..].iter() {
+            let c = try!(compare(a, b));
+            try!(writeln!(stream,
+                          "{}\t{}\t{:.6}\t{:.6}\t{:.6}\t{:.6}",
+                          a.name,
+                          b.name,
+                          c.jaccard,
+                          c.containment,
+                          c.containment_other,
+                          c.distance)
+                .chain_err(|| "Failed to write comparison"));
+        }
+    }
+    stream.flush().chain_err(|| "Failed to write comparison")
+}
@@ -1,76 +1,281 @@
-use std::cmp::Ordering;
 use std::fmt::Debug;
 
 use jobsteal::Spawner;
 
-fn quick_sort<K, V, F>(v: &mut [Option<(K, V)>], merge_dups: &F, spawner: Option<&Spawner>)
-    where K: Ord + Clone + Send + Debug,
-          V: Send + Debug,
-          F: Fn(&K, &mut V, V) + Sync
+/// Slices at least this long are radix sorted instead of quick sorted
+const RADIX_SORT_THRESHOLD: usize = 4 * 1024;
+/// The fewest entries a radix sort splits between jobs
+const RADIX_CHUNK_LEN: usize = 64 * 1024;
+/// Radix sort buckets at most this long are insertion sorted
+const INSERTION_SORT_LEN: usize = 32;
+
+/// Keys which can be radix sorted as their value as a u64
+pub trait RadixKey {
+    fn radix_key(&self) -> u64;
+}
+
+macro_rules! impl_radix_key {
+    ($($t:ty),*) => {
+        $(impl RadixKey for $t {
+            #[inline]
+            fn radix_key(&self) -> u64 {
+                *self as u64
+            }
+        })*
+    }
+}
+
+impl_radix_key!(u8, u16, u32, u64);
+
+fn quick_sort<K, V>(keys: &mut [K], values: &mut [V], spawner: Option<&Spawner>)
+    where K: Ord + Copy + Send + Debug,
+          V: Copy + Send + Debug
 {
-    if v.len() <= 1 {
+    if keys.len() <= 1 {
         return;
     }
 
-    if spawner.is_some() && v.len() <= 5 * 1024 {
-        return quick_sort(v, merge_dups, None);
+    if spawner.is_some() && keys.len() <= 5 * 1024 {
+        return quick_sort(keys, values, None);
     }
 
-    let split_point = partition(v, merge_dups);
-    let (lo, hi) = v.split_at_mut(split_point);
+    let split_point = partition(keys, values);
+    let (lo_keys, hi_keys) = keys.split_at_mut(split_point);
+    let (lo_values, hi_values) = values.split_at_mut(split_point);
 
     if let Some(spawner) = spawner {
-        spawner.join(|j| quick_sort(lo, merge_dups, Some(j)),
-                     |j| quick_sort(hi, merge_dups, Some(j)));
+        spawner.join(|j| quick_sort(lo_keys, lo_values, Some(j)),
+                     |j| quick_sort(hi_keys, hi_values, Some(j)));
     } else {
-        quick_sort(lo, merge_dups, None);
-        quick_sort(hi, merge_dups, None);
+        quick_sort(lo_keys, lo_values, None);
+        quick_sort(hi_keys, hi_values, None);
     }
 }
 
-pub fn sort<K, V, F>(v: &mut [Option<(K, V)>], merge_dups: F, spawner: Option<&Spawner>)
-    where K: Ord + Clone + Send + Debug,
-          V: Send + Debug,
+/// An in-place MSD radix sort on the keys, 8 bits at a time, starting from
+/// the highest digit any key uses (skipping the unused high bits of short
+/// k-mers). Each pass swaps entries into their buckets like an American flag
+/// sort, so no scratch copy of the input is needed, then the buckets are
+/// sorted on the next digit in parallel.
+///
+/// Duplicates are merged by the same pass: the buckets of the last digit, and
+/// the small buckets which are insertion sorted, are merged as they are
+/// finished, and each bucket's distinct entries are moved up behind the
+/// previous bucket's. Returns the number of distinct keys, which are sorted
+/// at the start of the slices.
+pub fn radix_sort<K, V, F>(keys: &mut [K],
+                           values: &mut [V],
+                           merge_dups: &F,
+                           spawner: Option<&Spawner>)
+                           -> usize
+    where K: RadixKey + Copy + Send + Sync,
+          V: Copy + Send + Sync,
           F: Fn(&K, &mut V, V) + Sync
 {
-    quick_sort(v, &merge_dups, spawner)
+    if keys.len() <= 1 {
+        return keys.len();
+    }
+
+    let key_bits = keys.iter().fold(0, |acc, k| acc | k.radix_key());
+    let digits = (64 - key_bits.leading_zeros() as usize).div_ceil(8);
+    msd_radix_sort(keys, values, digits.saturating_sub(1), merge_dups, spawner)
 }
 
-fn compare<K: Ord, V>(a: &Option<(K, V)>, b: &Option<(K, V)>) -> Ordering {
-    match *a {
-        Some(ref a) => {
-            match *b {
-                Some(ref b) => a.0.cmp(&b.0),
-                None => Ordering::Greater,
+#[inline]
+fn digit_of<K: RadixKey>(key: &K, shift: usize) -> usize {
+    ((key.radix_key() >> shift) & 0xff) as usize
+}
+
+fn insertion_sort<K: RadixKey + Copy, V: Copy>(keys: &mut [K], values: &mut [V]) {
+    for i in 1..keys.len() {
+        let (key, value) = (keys[i], values[i]);
+        let mut j = i;
+        while j > 0 && keys[j - 1].radix_key() > key.radix_key() {
+            keys[j] = keys[j - 1];
+            values[j] = values[j - 1];
+            j -= 1;
+        }
+        keys[j] = key;
+        values[j] = value;
+    }
+}
+
+fn msd_radix_sort<K, V, F>(keys: &mut [K],
+                           values: &mut [V],
+                           digit: usize,
+                           merge_dups: &F,
+                           spawner: Option<&Spawner>)
+                           -> usize
+    where K: RadixKey + Copy + Send + Sync,
+          V: Copy + Send + Sync,
+          F: Fn(&K, &mut V, V) + Sync
+{
+    if keys.len() <= INSERTION_SORT_LEN {
+        insertion_sort(keys, values);
+        return merge_runs(keys, values, merge_dups);
+    }
+
+    let shift = digit * 8;
+    let mut bucket_lens = [0usize; 256];
+    for key in keys.iter() {
+        bucket_lens[digit_of(key, shift)] += 1;
+    }
+
+    // When every key has the same digit there is nothing to move
+    if !bucket_lens.contains(&keys.len()) {
+        let mut heads = [0usize; 256];
+        let mut ends = [0usize; 256];
+        let mut start = 0;
+        for bucket in 0..256 {
+            heads[bucket] = start;
+            start += bucket_lens[bucket];
+            ends[bucket] = start;
+        }
+        // Swap each entry straight into the next free place of its bucket
+        for bucket in 0..256 {
+            while heads[bucket] < ends[bucket] {
+                let i = heads[bucket];
+                let target = digit_of(&keys[i], shift);
+                if target != bucket {
+                    let j = heads[target];
+                    keys.swap(i, j);
+                    values.swap(i, j);
+                }
+                heads[target] += 1;
             }
         }
-        None => Ordering::Less,
     }
+
+    // The keys of each bucket of the last digit are all equal
+    if digit == 0 {
+        return merge_runs(keys, values, merge_dups);
+    }
+    let mut distinct = Vec::with_capacity(256);
+    {
+        let mut buckets = Vec::with_capacity(256);
+        let mut rest_keys = &mut *keys;
+        let mut rest_values = &mut *values;
+        for &len in bucket_lens.iter().filter(|&&len| len > 0) {
+            let (bucket_keys, next_keys) = rest_keys.split_at_mut(len);
+            let (bucket_values, next_values) = rest_values.split_at_mut(len);
+            buckets.push((bucket_keys, bucket_values));
+            distinct.push(0);
+            rest_keys = next_keys;
+            rest_values = next_values;
+        }
+        sort_buckets(&mut buckets, &mut distinct, digit - 1, merge_dups, spawner);
+    }
+
+    // Close the gaps left by the merged duplicates of each bucket
+    let mut start = 0;
+    let mut end = 0;
+    for (&len, &distinct) in bucket_lens.iter().filter(|&&len| len > 0).zip(distinct.iter()) {
+        if start != end {
+            keys.copy_within(start..start + distinct, end);
+            values.copy_within(start..start + distinct, end);
+        }
+        start += len;
+        end += distinct;
+    }
+    end
 }
 
-fn partition<K: Ord + Clone + Send + Debug, V: Send + Debug, F: Fn(&K, &mut V, V) + Sync>
-    (v: &mut [Option<(K, V)>],
-     merge_dups: &F)
-     -> usize {
-    let pivot = v.len() - 1;
-    debug!("Partitioning {:?}", v);
-    let mut i = 0;
-    for j in 0..pivot {
-        let order = compare(&v[j], &v[pivot]);
-        if order == Ordering::Equal {
-            let old = v[j].take();
-            if let Some(&mut Some(ref mut pivot)) = v.get_mut(pivot) {
-                let old = old.unwrap();
-                merge_dups(&old.0, &mut pivot.1, old.1);
+/// Sorts the buckets of a radix sort pass on the given digit, splitting them
+/// between jobs while there are enough entries to be worth it, and sets the
+/// number of distinct keys left at the start of each bucket
+fn sort_buckets<K, V, F>(buckets: &mut [(&mut [K], &mut [V])],
+                         distinct: &mut [usize],
+                         digit: usize,
+                         merge_dups: &F,
+                         spawner: Option<&Spawner>)
+    where K: RadixKey + Copy + Send + Sync,
+          V: Copy + Send + Sync,
+          F: Fn(&K, &mut V, V) + Sync
+{
+    let len = buckets.iter().map(|bucket| bucket.0.len()).sum::<usize>();
+    match spawner {
+        Some(spawner) if buckets.len() > 1 && len >= RADIX_CHUNK_LEN => {
+            let mid = buckets.len() / 2;
+            let (lo, hi) = buckets.split_at_mut(mid);
+            let (lo_distinct, hi_distinct) = distinct.split_at_mut(mid);
+            spawner.join(|j| sort_buckets(lo, lo_distinct, digit, merge_dups, Some(j)),
+                         |j| sort_buckets(hi, hi_distinct, digit, merge_dups, Some(j)));
+        }
+        _ => {
+            for (bucket, distinct) in buckets.iter_mut().zip(distinct.iter_mut()) {
+                *distinct = msd_radix_sort(bucket.0, bucket.1, digit, merge_dups, spawner);
             }
         }
-        if order != Ordering::Greater {
-            v.swap(i, j);
+    }
+}
+
+/// Merges runs of equal keys in sorted input into the first of each run,
+/// moved up to the start. Returns the number of distinct keys.
+fn merge_runs<K, V, F>(keys: &mut [K], values: &mut [V], merge_dups: &F) -> usize
+    where K: RadixKey + Copy,
+          V: Copy,
+          F: Fn(&K, &mut V, V)
+{
+    if keys.is_empty() {
+        return 0;
+    }
+    let mut last = 0;
+    for i in 1..keys.len() {
+        if keys[i].radix_key() == keys[last].radix_key() {
+            let value = values[i];
+            merge_dups(&keys[i], &mut values[last], value);
+        } else {
+            last += 1;
+            keys[last] = keys[i];
+            values[last] = values[i];
+        }
+    }
+    last + 1
+}
+
+/// Sorts the keys and values together, merging the values of duplicate keys.
+/// Large inputs are radix sorted, which doesn't degrade on sorted or
+/// low diversity input like the quick sort does, and merges duplicates in the
+/// same pass.
+pub fn sort<K, V, F>(keys: &mut Vec<K>, values: &mut Vec<V>, merge_dups: F, spawner: Option<&Spawner>)
+    where K: RadixKey + Ord + Copy + Send + Sync + Debug,
+          V: Copy + Send + Sync + Debug,
+          F: Fn(&K, &mut V, V) + Sync
+{
+    assert_eq!(keys.len(), values.len());
+    let distinct = if keys.len() >= RADIX_SORT_THRESHOLD {
+        radix_sort(keys.as_mut_slice(), values.as_mut_slice(), &merge_dups, spawner)
+    } else {
+        quick_sort(keys.as_mut_slice(), values.as_mut_slice(), spawner);
+        merge_runs(keys.as_mut_slice(), values.as_mut_slice(), &merge_dups)
+    };
+    keys.truncate(distinct);
+    values.truncate(distinct);
+    // Shrink the vectors if merging freed up much of their memory
+    if keys.len() < keys.capacity() / 2 {
+        keys.shrink_to_fit();
+        values.shrink_to_fit();
+    }
+}
+
+fn partition<K: Ord + Copy + Send + Debug, V: Copy + Send + Debug>(keys: &mut [K],
+                                                                   values: &mut [V])
+                                                                   -> usize {
+    let pivot = keys.len() - 1;
+    // Pivot on the middle, so already sorted input splits evenly
+    keys.swap(keys.len() / 2, pivot);
+    values.swap(keys.len() / 2, pivot);
+    debug!("Partitioning {:?}", keys);
+    let mut i = 0;
+    for j in 0..pivot {
+        if keys[j] <= keys[pivot] {
+            keys.swap(i, j);
+            values.swap(i, j);
             i += 1;
         }
-        debug!("Partition iteration done: i={}, j={}, v={:?}", i, j, v);
     }
-    v.swap(i, pivot);
-    debug!("After partition at {}: {:?}", i, v);
+    keys.swap(i, pivot);
+    values.swap(i, pivot);
+    debug!("After partition at {}: {:?}", i, keys);
     i
 }
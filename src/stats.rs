@@ -0,0 +1,161 @@
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::io::BufWriter;
+use std::time::{Duration, Instant};
+
+use errors::*;
+use sink::Sink;
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum Format {
+    /// A `name\tvalue` line per statistic
+    Tsv,
+    Json,
+}
+
+/// A summary of a run, written by `--stats`
+#[derive(Default)]
+pub struct Stats {
+    /// The name and number of records of each input
+    pub records: Vec<(String, u64)>,
+    pub bases: u64,
+    pub invalid: u64,
+    /// Every k-mer of the written counts, including duplicates. The counts
+    /// left unknown by the run's options are `None`, and written as such.
+    pub kmers: Option<u64>,
+    pub distinct: Option<u64>,
+    pub singletons: Option<u64>,
+    pub max_count: Option<u16>,
+    pub read_time: Duration,
+    pub count_time: Duration,
+    /// Merging the counts, including writing the k-mers as they stream out
+    pub consolidate_time: Duration,
+    /// Writing what the output held back until every k-mer was seen
+    pub output_time: Duration,
+}
+
+fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+impl Stats {
+    fn values(&self) -> Vec<(&'static str, Option<String>)> {
+        let seconds = |time: Duration| Some(format!("{:.3}", time.as_secs_f64()));
+        vec![("bases", Some(self.bases.to_string())),
+             ("invalid_characters", Some(self.invalid.to_string())),
+             ("kmers", self.kmers.map(|n| n.to_string())),
+             ("distinct_kmers", self.distinct.map(|n| n.to_string())),
+             ("singletons", self.singletons.map(|n| n.to_string())),
+             ("max_count", self.max_count.map(|n| n.to_string())),
+             ("read_seconds", seconds(self.read_time)),
+             ("count_seconds", seconds(self.count_time)),
+             ("consolidate_seconds", seconds(self.consolidate_time)),
+             ("output_seconds", seconds(self.output_time))]
+    }
+
+    pub fn write<W: Write>(&self, mut stream: W, format: Format) -> io::Result<()> {
+        match format {
+            Format::Tsv => {
+                for &(ref input, records) in self.records.iter() {
+                    try!(writeln!(stream, "records:{}\t{}", input, records));
+                }
+                for (name, value) in self.values() {
+                    let value = value.unwrap_or_else(|| "NA".to_string());
+                    try!(writeln!(stream, "{}\t{}", name, value));
+                }
+            }
+            Format::Json => {
+                try!(write!(stream, "{{\"records\":{{"));
+                for (i, &(ref input, records)) in self.records.iter().enumerate() {
+                    let separator = if i == 0 { "" } else { "," };
+                    try!(write!(stream, "{}{}:{}", separator, json_string(input), records));
+                }
+                try!(write!(stream, "}}"));
+                for (name, value) in self.values() {
+                    let value = value.unwrap_or_else(|| "null".to_string());
+                    try!(write!(stream, ",\"{}\":{}", name, value));
+                }
+                try!(writeln!(stream, "}}"));
+            }
+        }
+        stream.flush()
+    }
+
+    pub fn save(&self, path: &str, format: Format) -> Result<()> {
+        let file = try!(File::create(path).chain_err(|| "Failed to create stats file"));
+        self.write(BufWriter::new(file), format).chain_err(|| "Failed to write stats file")
+    }
+}
+
+/// Tallies the final counts on their way to a sink, and times how long it
+/// takes for all of them to arrive and for the sink to finish
+pub struct Tally<S> {
+    inner: S,
+    pub kmers: u64,
+    pub distinct: u64,
+    pub singletons: u64,
+    pub max_count: u16,
+    started: Instant,
+    finishing: Option<(Instant, Duration)>,
+}
+
+impl<S: Sink> Tally<S> {
+    /// `started` is when consolidation started, usually just before the sink
+    /// is created
+    pub fn new(inner: S, started: Instant) -> Tally<S> {
+        Tally {
+            inner: inner,
+            kmers: 0,
+            distinct: 0,
+            singletons: 0,
+            max_count: 0,
+            started: started,
+            finishing: None,
+        }
+    }
+
+    /// The time until the last k-mer was written, which includes writing
+    /// the k-mers the sink doesn't hold back
+    pub fn consolidate_time(&self) -> Duration {
+        self.finishing.map_or_else(|| self.started.elapsed(), |(at, _)| at - self.started)
+    }
+
+    /// The time the sink took to finish
+    pub fn output_time(&self) -> Duration {
+        self.finishing.map_or(Duration::from_secs(0), |(_, took)| took)
+    }
+}
+
+impl<S: Sink> Sink for Tally<S> {
+    #[inline]
+    fn write(&mut self, kmer: u64, count: u16) -> Result<()> {
+        self.kmers += count as u64;
+        if count > 0 {
+            self.distinct += 1;
+        }
+        if count == 1 {
+            self.singletons += 1;
+        }
+        self.max_count = ::std::cmp::max(self.max_count, count);
+        self.inner.write(kmer, count)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let at = Instant::now();
+        let finished = self.inner.finish();
+        self.finishing = Some((at, at.elapsed()));
+        finished
+    }
+}
@@ -0,0 +1,36 @@
+use alphabet::{Alphabet, Dna, Protein, Rna};
+use get_kmers::{SliceKmers, Masking};
+use kmer_length::KmerLength;
+
+#[test]
+fn protein_kmers() {
+    let mut kmers = Vec::new();
+    SliceKmers::new(KmerLength::<Protein>::new(2), Masking::All)
+        .for_each(b"MKv*W", |kmer| kmers.push(kmer));
+    let (m, k, v, w) = (11, 9, 20, 21);
+    // The invalid * is skipped over
+    assert_eq!(kmers, vec![(m << 5) | k, (k << 5) | v, (v << 5) | w]);
+}
+
+#[test]
+fn encoding_is_sorted() {
+    for alphabet in [&b"ABCDEFGHIKLMNOPQRSTUVWXYZ"[..], &b"ACGU"[..]].iter() {
+        let codes = alphabet.iter()
+            .map(|&c| if alphabet.len() == 4 { Rna::encode(c) } else { Protein::encode(c) })
+            .map(Option::unwrap)
+            .collect::<Vec<_>>();
+        assert!(codes.windows(2).all(|w| w[0] < w[1]));
+        for (&c, &code) in alphabet.iter().zip(codes.iter()) {
+            let decoded = if alphabet.len() == 4 { Rna::decode(code) } else { Protein::decode(code) };
+            assert_eq!(decoded, c);
+        }
+    }
+}
+
+#[test]
+fn reverse_complement() {
+    let kmer_len = KmerLength::<Dna>::new(3);
+    // AAC -> GTT
+    assert_eq!(kmer_len.reverse_complement(0b000001), 0b101111);
+    assert_eq!(kmer_len.canonical(0b101111), 0b000001);
+}
@@ -0,0 +1,43 @@
+use std::io::Cursor;
+
+use database;
+use alphabet::{Alphabet, Dna, Protein};
+use kmer_length::KmerLength;
+use sink::Sink;
+
+fn write<A: Alphabet>(kmer_len: KmerLength<A>, inputs: &[String], counts: &[(u64, u16)]) -> Vec<u8> {
+    let mut buf = Cursor::new(Vec::new());
+    {
+        let mut writer = database::Writer::new(&mut buf, kmer_len, inputs).unwrap();
+        for &(kmer, count) in counts {
+            writer.write(kmer, count).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+    buf.into_inner()
+}
+
+#[test]
+fn round_trip() {
+    let inputs = vec!["a.fasta".to_string(), "-".to_string()];
+    let counts = vec![(0b0001, 3), (0b0110, 1), (0b1111, 65535)];
+    let buf = write(KmerLength::<Dna>::new(2), inputs.as_slice(), counts.as_slice());
+
+    let db = database::read::<_, Dna>(buf.as_slice()).unwrap();
+    assert_eq!(db.kmer_len.length(), 2);
+    assert_eq!(db.inputs, inputs);
+    assert_eq!(db.counts.iter().collect::<Vec<_>>(), counts);
+}
+
+#[test]
+fn rejects_truncated() {
+    let mut buf = write(KmerLength::<Dna>::new(2), &[], &[(1, 1)]);
+    buf.pop();
+    assert!(database::read::<_, Dna>(buf.as_slice()).is_err());
+}
+
+#[test]
+fn rejects_other_alphabet() {
+    let buf = write(KmerLength::<Dna>::new(2), &[], &[(1, 1)]);
+    assert!(database::read::<_, Protein>(buf.as_slice()).is_err());
+}
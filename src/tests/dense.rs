@@ -0,0 +1,53 @@
+use alphabet::{Dna, Protein};
+use dense;
+use kmer_length::KmerLength;
+use errors::*;
+use sink::Sink;
+
+struct Collect(Vec<(u64, u16)>);
+
+impl Sink for Collect {
+    fn write(&mut self, kmer: u64, count: u16) -> Result<()> {
+        self.0.push((kmer, count));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn merges_tables() {
+    let mut pool = ::jobsteal::make_pool(2).unwrap();
+    let tables = dense::Tables::new(2);
+    let mut a = tables.take();
+    let mut b = tables.take();
+    dense::increment(&mut a, 1);
+    dense::increment(&mut a, 3);
+    dense::increment(&mut b, 3);
+    b[1] = ::std::u16::MAX;
+    tables.put_back(a);
+    tables.put_back(b);
+    let table = tables.merge(&pool.spawner());
+
+    let mut sink = Collect(Vec::new());
+    assert_eq!(dense::write_all(&table, KmerLength::<Dna>::new(1), &mut sink, false, false).unwrap(), 2);
+    assert_eq!(sink.0, vec![(1, ::std::u16::MAX), (3, 2)]);
+
+    let mut sink = Collect(Vec::new());
+    assert_eq!(dense::write_all(&table, KmerLength::<Dna>::new(1), &mut sink, true, true).unwrap(), 4);
+    assert_eq!(sink.0, vec![(0, 0), (1, 1), (2, 0), (3, 1)]);
+}
+
+#[test]
+fn protein_zero_counts_skip_unused_codes() {
+    let kmer_len = KmerLength::<Protein>::new(2);
+    let mut table = vec![0; 1 << 10];
+    // AB
+    dense::increment(&mut table, 0b00000_00001);
+    let mut sink = Collect(Vec::new());
+    assert_eq!(dense::write_all(&table, kmer_len, &mut sink, true, false).unwrap(), 25 * 25);
+    assert!(sink.0.iter().all(|&(kmer, _)| kmer & 31 < 25 && kmer >> 5 < 25));
+    assert_eq!(sink.0[1], (1, 1));
+}
@@ -0,0 +1,47 @@
+use alphabet::Dna;
+use filter::Matcher;
+use kmer_length::KmerLength;
+use parsers::records;
+
+#[test]
+fn canonical_matches() {
+    let kmer_len = KmerLength::<Dna>::new(3);
+    // ACG and the canonical form of GGT (ACC)
+    let mut reference = vec![0b000110, 0b000101];
+    reference.sort();
+    let mut matcher = Matcher::new(reference, kmer_len, true);
+    assert_eq!(matcher.matches(b"ACGT"), 2);
+    assert_eq!(matcher.matches(b"GGTT"), 1);
+    assert_eq!(matcher.matches(b"TTTT"), 0);
+}
+
+#[test]
+fn fastq_round_trip() {
+    let input = b"\n@r1 x\nACGT\n+\nIIII\n@r2\nGG\n+r2\n#!\n";
+    let records = records::from_reader(&input[..])
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[1].qual, Some(b"#!".to_vec()));
+    let mut output = Vec::new();
+    for record in records.iter() {
+        record.write(&mut output).unwrap();
+    }
+    assert_eq!(output, b"@r1 x\nACGT\n+\nIIII\n@r2\nGG\n+\n#!\n".to_vec());
+}
+
+#[test]
+fn fastq_length_mismatch() {
+    let input = &b"@r1\nACGT\n+\nIII\n"[..];
+    assert!(records::from_reader(input).unwrap().next().unwrap().is_err());
+}
+
+#[test]
+fn mate_names() {
+    assert_eq!(records::mate_name(b"read7/1"), b"read7");
+    assert_eq!(records::mate_name(b"read7/2"), b"read7");
+    assert_eq!(records::mate_name(b"read7/3"), b"read7/3");
+    assert_eq!(records::mate_name(b"read7"), b"read7");
+    assert_eq!(records::mate_name(b"/1"), b"/1");
+}
@@ -0,0 +1,42 @@
+use alphabet::Dna;
+use get_kmers::{SliceKmers, Masking};
+use kmer_length::KmerLength;
+
+fn kmers(input: &[u8], masking: Masking) -> Vec<u64> {
+    let mut kmers = Vec::new();
+    SliceKmers::new(KmerLength::<Dna>::new(2), masking).for_each(input, |kmer| kmers.push(kmer));
+    kmers
+}
+
+#[test]
+fn soft_masking() {
+    let input = b"ACgtaCG";
+    assert_eq!(kmers(input, Masking::All),
+               vec![0b0001, 0b0110, 0b1011, 0b1100, 0b0001, 0b0110]);
+    assert_eq!(kmers(input, Masking::SkipSoftMasked), vec![0b0001, 0b0110]);
+    assert_eq!(kmers(input, Masking::OnlySoftMasked), vec![0b1011, 0b1100]);
+}
+
+#[test]
+fn invalid_characters_are_skipped() {
+    assert_eq!(kmers(b"ANC", Masking::All), vec![0b0001]);
+}
+
+#[test]
+fn whitespace_is_skipped() {
+    assert_eq!(kmers(b"AC\nG T\r\n", Masking::All), vec![0b0001, 0b0110, 0b1011]);
+}
+
+#[test]
+fn several_lengths() {
+    let text = b"ACGTNACGTACGTTGCA".repeat(3);
+    let lengths = [KmerLength::<Dna>::new(2), KmerLength::new(5), KmerLength::new(3)];
+    let mut found = vec![Vec::new(); lengths.len()];
+    SliceKmers::new(lengths[1], Masking::All)
+        .for_each_length(&text, &lengths, 0, |i, kmer| found[i].push(kmer));
+    for (&length, found) in lengths.iter().zip(found) {
+        let mut expected = Vec::new();
+        SliceKmers::new(length, Masking::All).for_each(&text, |kmer| expected.push(kmer));
+        assert_eq!(found, expected);
+    }
+}
@@ -0,0 +1,22 @@
+use jellyfish::read_dump;
+use alphabet::Dna;
+use kmer_length::KmerLength;
+
+#[test]
+fn fasta_dump() {
+    let dump = b">3\nACG\n>70000\nTTT\n";
+    assert_eq!(read_dump(&dump[..], KmerLength::<Dna>::new(3)).unwrap().iter().collect::<Vec<_>>(),
+               vec![(0b000110, 3), (0b111111, 65535)]);
+}
+
+#[test]
+fn column_dump() {
+    let dump = b"ACG 3\nCAT\t1\n";
+    assert_eq!(read_dump(&dump[..], KmerLength::<Dna>::new(3)).unwrap().iter().collect::<Vec<_>>(),
+               vec![(0b000110, 3), (0b010011, 1)]);
+}
+
+#[test]
+fn wrong_length() {
+    assert!(read_dump(&b"ACGT 3\n"[..], KmerLength::<Dna>::new(3)).is_err());
+}
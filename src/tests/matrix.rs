@@ -0,0 +1,14 @@
+use matrix;
+
+#[test]
+fn shared() {
+    assert_eq!(matrix::shared(&[1, 3, 5, 7], &[2, 3, 4, 7, 9]), 2);
+    assert_eq!(matrix::shared(&[], &[1]), 0);
+}
+
+#[test]
+fn symmetric_matrix() {
+    let sets = vec![vec![1, 2, 3], vec![2, 3, 4, 5], vec![9]];
+    let shared = matrix::shared_matrix(&sets, 2);
+    assert_eq!(shared, vec![vec![3, 2, 0], vec![2, 4, 0], vec![0, 0, 1]]);
+}
@@ -1 +1,19 @@
 mod sort;
+mod database;
+mod jellyfish;
+mod alphabet;
+mod translate;
+mod get_kmers;
+mod planner;
+mod dense;
+mod multifasta;
+mod simd;
+mod sink;
+mod sketch;
+mod matrix;
+mod profile;
+mod filter;
+mod unitigs;
+mod sam;
+mod pipeline;
+mod stats;
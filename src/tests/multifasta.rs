@@ -0,0 +1,25 @@
+use parsers::multifasta::{split_record, Sections};
+
+#[test]
+fn sections() {
+    let input = b">a\nAC\nGT\n>b desc\n\n>c\nTT";
+    let sections = Sections::new(input).collect::<Vec<_>>();
+    assert_eq!(sections, vec![&b"AC\nGT\n"[..], &b"\n"[..], &b"TT"[..]]);
+}
+
+#[test]
+fn no_sections() {
+    assert_eq!(Sections::new(b"").count(), 0);
+    assert_eq!(Sections::new(b">header only").count(), 0);
+}
+
+#[test]
+fn split_records() {
+    let first = split_record(b"a desc\nAC\nGT\n>b\nTT").unwrap();
+    assert_eq!(first.header, b"a desc");
+    assert_eq!(first.seq, b"AC\nGT\n");
+    assert_eq!(first.rest, Some(&b"b\nTT"[..]));
+    let last = split_record(first.rest.unwrap()).unwrap();
+    assert_eq!((last.header, last.seq, last.rest), (&b"b"[..], &b"TT"[..], None));
+    assert!(split_record(b"header only").is_none());
+}
@@ -0,0 +1,51 @@
+use std::io::Cursor;
+
+use alphabet::Dna;
+use get_kmers::{Masking, SliceKmers};
+use kmer_length::KmerLength;
+use parsers::Format;
+use pipeline::{run, Input, Overlap};
+
+/// A record several blocks long, with an N every so often, and a short one
+fn long_records() -> Vec<u8> {
+    let mut data = b">long\n".to_vec();
+    for line in 0..200_000 {
+        let bases = if line % 7 == 0 { b"ACGTTGCANGTACCATGGAT" } else { b"ACGTTGCAAGTACCATGGAT" };
+        for _ in 0..3 {
+            data.extend_from_slice(bases);
+        }
+        data.push(b'\n');
+    }
+    data.extend_from_slice(b">short\nACGTACGT\n");
+    data
+}
+
+#[test]
+fn long_records_are_split_once() {
+    let kmer_len = KmerLength::<Dna>::new(9);
+    let data = long_records();
+    let states = (0..3).map(|_| (SliceKmers::new(kmer_len, Masking::All), 0)).collect();
+    let input = Input::Stream(Box::new(Cursor::new(data.clone())));
+    let overlap = Overlap::kmers(kmer_len);
+    let (states, summary) = run(vec![input], Format::Fasta, overlap, 1, states, |state, record, skip| {
+            let (ref mut kmers, ref mut count) = *state;
+            kmers.for_each_length(record, &[kmer_len], skip, |_, _| *count += 1);
+            Ok(())
+        })
+        .unwrap();
+    // K-mers skip over an N, and the short record has none
+    let bases = data.iter().filter(|c| b"ACGT".contains(c)).count() - 8;
+    let total = states.iter().map(|&(_, count)| count).sum::<usize>();
+    assert_eq!(total, bases - 8);
+    assert_eq!(summary.records, vec![2]);
+}
+
+#[test]
+fn panicking_counters_stop_the_pipeline() {
+    let input = Input::Stream(Box::new(Cursor::new(long_records().repeat(3))));
+    let overlap = Overlap::kmers(KmerLength::<Dna>::new(9));
+    let result = run(vec![input], Format::Fasta, overlap, 1, vec![()], |_, _, _| {
+        panic!("counting failed")
+    });
+    assert!(result.is_err());
+}
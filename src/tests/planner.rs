@@ -0,0 +1,30 @@
+use kmer_counts::KmerCounts;
+use kmer_tree::{JoinMethod, Leaf, Node};
+use planner::plan;
+
+fn leaf(len: usize, distinct: u64) -> Node {
+    Node::Leaf(Leaf {
+        counts: (0..len as u64).map(|i| (i % distinct, 1)).collect::<KmerCounts>(),
+        sorted: false,
+    })
+}
+
+#[test]
+fn small_inputs_are_concatenated() {
+    let root = Node::Branch(vec![Node::Branch(vec![leaf(100, 100), leaf(100, 100)])]);
+    assert!(plan(&root, 4, true) == vec![JoinMethod::Sort, JoinMethod::Concat]);
+}
+
+#[test]
+fn large_duplicated_inputs_are_merged_early() {
+    let input = || Node::Branch(vec![leaf(2 * 1024 * 1024, 16)]);
+    let root = Node::Branch(vec![input(), input()]);
+    assert!(plan(&root, 4, true) == vec![JoinMethod::Sort, JoinMethod::Sort]);
+    assert!(plan(&root, 1, true) == vec![JoinMethod::Sort, JoinMethod::Join]);
+}
+
+#[test]
+fn unordered_output_isnt_sorted() {
+    let root = Node::Branch(vec![leaf(100, 100), leaf(100, 100)]);
+    assert!(plan(&root, 4, false) == vec![JoinMethod::Concat]);
+}
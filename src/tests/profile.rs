@@ -0,0 +1,69 @@
+use profile::{summarize, write_profile, Summary};
+use parsers::records::{FastaRecords, Record};
+
+#[test]
+fn summaries() {
+    assert_eq!(summarize(&[5, 1, 3, 0], 2),
+               Some(Summary {
+                   min: 0,
+                   median: 1,
+                   below: 0.5,
+               }));
+    assert_eq!(summarize(&[], 2), None);
+}
+
+#[test]
+fn fasta_records() {
+    let input = &b"\n>r1 first read\nAC\r\nGT\n>r2\n>r3\nT"[..];
+    let records = FastaRecords::new(input).collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(records,
+               vec![Record {
+                        header: b"r1 first read".to_vec(),
+                        seq: b"ACGT".to_vec(),
+                        qual: None,
+                    },
+                    Record {
+                        header: b"r2".to_vec(),
+                        seq: Vec::new(),
+                        qual: None,
+                    },
+                    Record {
+                        header: b"r3".to_vec(),
+                        seq: b"T".to_vec(),
+                        qual: None,
+                    }]);
+    assert_eq!(records[0].id(), b"r1");
+    assert!(FastaRecords::new(&b"ACGT\n"[..]).next().unwrap().is_err());
+}
+
+#[test]
+fn mate_suffix_only_stripped_from_pairs() {
+    let read = |header: &[u8]| {
+        Record {
+            header: header.to_vec(),
+            seq: b"ACGT".to_vec(),
+            qual: None,
+        }
+    };
+    let mut out = Vec::new();
+    write_profile(&mut out, &[read(b"r1/1")], &[2], 2).unwrap();
+    write_profile(&mut out, &[read(b"r2/1"), read(b"r2/2")], &[1, 3], 2).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(),
+               "r1/1\t1\t2\t2\t0.000000\t2\nr2\t2\t1\t1\t0.500000\t1,3\n");
+}
+
+#[test]
+fn long_fasta_records() {
+    let seq = b"ACGT".repeat(50_000);
+    let mut input = b">long\n".to_vec();
+    for line in seq.chunks(60) {
+        input.extend_from_slice(line);
+        input.push(b'\n');
+    }
+    input.extend_from_slice(b">last");
+    let records = FastaRecords::new(&input[..]).collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].seq, seq);
+    assert_eq!(records[1].header, b"last");
+    assert!(records[1].seq.is_empty());
+}
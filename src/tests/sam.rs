@@ -0,0 +1,28 @@
+use parsers::sam::Sections;
+
+const INPUT: &[u8] = b"@HD\tVN:1.6\n\
+@SQ\tSN:chr1\tLN:100\n\
+r1\t0\tchr1\t1\t60\t4M\t*\t0\t0\tACGG\tIIII\n\
+r2\t16\tchr1\t5\t60\t4M\t*\t0\t0\tAACt\tIIII\r\n\
+r1\t256\tchr1\t9\t0\t4M\t*\t0\t0\t*\t*\n\
+r3\t2048\tchr1\t20\t60\t3M\t*\t0\t0\tGGG\tIII\n\
+r4\t4\t*\t0\t0\t*\t*\t0\t0\tTTAN\tIIII";
+
+#[test]
+fn sections() {
+    let sections = Sections::new(INPUT, false).map(|seq| seq.unwrap().into_owned()).collect::<Vec<_>>();
+    assert_eq!(sections,
+               vec![b"ACGG".to_vec(), b"aGTT".to_vec(), b"GGG".to_vec(), b"TTAN".to_vec()]);
+}
+
+#[test]
+fn primary_only() {
+    let sections = Sections::new(INPUT, true).map(|seq| seq.unwrap().into_owned()).collect::<Vec<_>>();
+    assert_eq!(sections, vec![b"ACGG".to_vec(), b"aGTT".to_vec(), b"TTAN".to_vec()]);
+}
+
+#[test]
+fn truncated_record() {
+    let mut sections = Sections::new(b"r1\t0\tchr1\t1\t60\n", false);
+    assert!(sections.next().unwrap().is_err());
+}
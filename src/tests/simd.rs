@@ -0,0 +1,77 @@
+use alphabet::{Alphabet, Dna, Rna};
+use get_kmers::{Encoder, Masking, SliceKmers};
+use kmer_length::KmerLength;
+use simd;
+
+/// Bases, soft-masked bases, line breaks and invalid characters
+fn text(len: usize) -> Vec<u8> {
+    let symbols = b"ACGTACGTACGTacgtNnU\n>*";
+    let mut state = 12345u64;
+    (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            symbols[(state >> 33) as usize % symbols.len()]
+        })
+        .collect()
+}
+
+fn levels() -> Vec<simd::Level> {
+    [simd::Level::Scalar, simd::Level::Sse2, simd::Level::Avx2]
+        .iter()
+        .cloned()
+        .filter(|level| level.supported())
+        .collect()
+}
+
+fn kmers<A: Alphabet>(text: &[u8], masking: Masking, level: simd::Level) -> Vec<u64> {
+    let mut kmers = Vec::new();
+    SliceKmers::with_level(KmerLength::<A>::new(5), masking, level)
+        .for_each(text, |kmer| kmers.push(kmer));
+    kmers
+}
+
+#[test]
+fn levels_agree() {
+    let text = text(10000);
+    for &masking in [Masking::All, Masking::SkipSoftMasked, Masking::OnlySoftMasked].iter() {
+        let dna = kmers::<Dna>(&text, masking, simd::Level::Scalar);
+        let rna = kmers::<Rna>(&text, masking, simd::Level::Scalar);
+        assert!(!dna.is_empty());
+        for &level in levels().iter() {
+            assert_eq!(kmers::<Dna>(&text, masking, level), dna);
+            assert_eq!(kmers::<Rna>(&text, masking, level), rna);
+        }
+    }
+}
+
+#[test]
+fn pack_matches_table() {
+    let text = text(simd::CHUNK);
+    let encoder = Encoder::new::<Dna>(Masking::All);
+    let letters = simd::Letters::from_table(encoder.table()).unwrap();
+    for &level in levels().iter() {
+        let packed = unsafe { simd::pack(level, &letters, &text) };
+        for (i, &c) in text.iter().enumerate() {
+            let valid = (packed.valid >> i) & 1 == 1;
+            assert_eq!(valid, Dna::encode(c).is_some());
+            if valid {
+                assert_eq!(packed.code(i), Dna::encode(c).unwrap());
+            }
+        }
+    }
+}
+
+#[test]
+fn rolled_canonical_kmers_match_reverse_complement() {
+    let text = text(10000);
+    for &k in [1, 5, 31, 32].iter() {
+        let kmer_len = KmerLength::<Dna>::new(k);
+        let mut expected = Vec::new();
+        SliceKmers::new(kmer_len, Masking::All)
+            .for_each(&text, |kmer| expected.push(kmer_len.canonical(kmer)));
+        let mut canonical = Vec::new();
+        SliceKmers::new(kmer_len, Masking::All)
+            .for_each_canonical(&text, |kmer| canonical.push(kmer));
+        assert_eq!(canonical, expected);
+    }
+}
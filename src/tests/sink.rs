@@ -0,0 +1,31 @@
+use alphabet::Dna;
+use kmer_length::KmerLength;
+use sink::{MinCount, Sink, Top};
+
+#[test]
+fn top_kmers() {
+    let mut out = Vec::new();
+    {
+        let mut top = Top::new(&mut out, KmerLength::<Dna>::new(2), 2, true);
+        for &(kmer, count) in [(0b0000, 1), (0b0001, 5), (0b0110, 2), (0b1011, 2)].iter() {
+            top.write(kmer, count).unwrap();
+        }
+        top.finish().unwrap();
+    }
+    assert_eq!(String::from_utf8(out).unwrap(),
+               "AC\t5\t5.0000e-1\tAC\nCG\t2\t2.0000e-1\tCG\n");
+}
+
+#[test]
+fn top_kmers_above_min_count() {
+    let mut out = Vec::new();
+    {
+        let mut top = MinCount::new(Top::new(&mut out, KmerLength::<Dna>::new(2), 3, false), 2);
+        for &(kmer, count) in [(0b0000, 1), (0b0001, 5), (0b0110, 3)].iter() {
+            top.write(kmer, count).unwrap();
+        }
+        top.finish().unwrap();
+    }
+    assert_eq!(String::from_utf8(out).unwrap(),
+               "AC\t5\t6.2500e-1\nCG\t3\t3.7500e-1\n");
+}
@@ -0,0 +1,54 @@
+use sketch;
+use sketch::{Builder, Mode, Sketch};
+
+fn sketch(name: &str, mode: Mode, kmers: ::std::ops::Range<u64>) -> Sketch {
+    let mut builder = Builder::new(mode);
+    for kmer in kmers {
+        builder.add(sketch::hash(kmer));
+    }
+    Sketch {
+        name: name.to_string(),
+        alphabet: "dna".to_string(),
+        kmer_len: 21,
+        canonical: true,
+        mode: mode,
+        hashes: builder.into_hashes(),
+    }
+}
+
+#[test]
+fn bottom_keeps_smallest() {
+    let mut builder = Builder::new(Mode::Bottom(3));
+    for &hash in &[9, 4, 7, 4, 1, 8, 2] {
+        builder.add(hash);
+    }
+    assert_eq!(builder.into_hashes(), vec![1, 2, 4]);
+}
+
+#[test]
+fn estimates() {
+    for &mode in &[Mode::Bottom(2000), Mode::Scaled(10)] {
+        let a = sketch("a", mode, 0..20000);
+        let b = sketch("b", mode, 10000..20000);
+        let same = sketch::compare(&a, &a).unwrap();
+        assert_eq!(same.jaccard, 1.0);
+        assert_eq!(same.distance, 0.0);
+        let half = sketch::compare(&a, &b).unwrap();
+        assert!((half.jaccard - 0.5).abs() < 0.05, "{:?}", half);
+        assert!((half.containment - 0.5).abs() < 0.05, "{:?}", half);
+        assert!(half.containment_other > 0.99, "{:?}", half);
+        let disjoint = sketch::compare(&a, &sketch("c", mode, 30000..40000)).unwrap();
+        assert_eq!(disjoint.jaccard, 0.0);
+        assert_eq!(disjoint.distance, 1.0);
+    }
+}
+
+#[test]
+fn round_trip() {
+    let sketches = vec![sketch("a", Mode::Bottom(100), 0..1000), sketch("b", Mode::Scaled(4), 0..1000)];
+    let mut buf = Vec::new();
+    sketch::write(&mut buf, &sketches).unwrap();
+    assert_eq!(sketch::read(buf.as_slice()).unwrap(), sketches);
+    buf.pop();
+    assert!(sketch::read(buf.as_slice()).is_err());
+}
@@ -1,4 +1,5 @@
 use sort::*;
+use kmer_counts::{add_counts, KmerCounts};
 
 const SORT_INPUT: [(u8, u8); 8] = [(0b11100001, 1),
                                    (0b01011000, 1),
@@ -9,13 +10,17 @@ const SORT_INPUT: [(u8, u8); 8] = [(0b11100001, 1),
                                    (0b00010100, 1),
                                    (0b00010100, 2)];
 
+fn sort_input() -> (Vec<u8>, Vec<u8>) {
+    SORT_INPUT.iter().cloned().unzip()
+}
+
 #[test]
 fn simple_sort() {
     ::env_logger::init().ok();
 
-    let mut input = SORT_INPUT.iter().cloned().map(Some).collect::<Vec<_>>();
-    sort(input.as_mut_slice(), |_, _, _| {}, None);
-    assert_eq!(input.into_iter().filter_map(|n| n).map(|n| n.0).collect::<Vec<_>>(),
+    let (mut keys, mut values) = sort_input();
+    sort(&mut keys, &mut values, |_, _, _| {}, None);
+    assert_eq!(keys,
                vec![0b00010100, 0b01011000, 0b01111100, 0b10000001, 0b10100100, 0b11100001]);
 }
 
@@ -23,9 +28,9 @@ fn simple_sort() {
 fn merge_dups() {
     ::env_logger::init().ok();
 
-    let mut input = SORT_INPUT.iter().cloned().map(Some).collect::<Vec<_>>();
-    sort(input.as_mut_slice(), |_, acc, other| *acc += other, None);
-    assert_eq!(input.into_iter().filter_map(|n| n).collect::<Vec<_>>(),
+    let (mut keys, mut values) = sort_input();
+    sort(&mut keys, &mut values, |_, acc, other| *acc += other, None);
+    assert_eq!(keys.into_iter().zip(values).collect::<Vec<_>>(),
                vec![(0b00010100, 3),
                     (0b01011000, 1),
                     (0b01111100, 3),
@@ -33,3 +38,73 @@ fn merge_dups() {
                     (0b10100100, 1),
                     (0b11100001, 1)]);
 }
+
+#[test]
+fn merged_counts_saturate() {
+    let mut counts = vec![(1, 65000), (2, 3), (1, 1000)].into_iter().collect::<KmerCounts>();
+    counts.sort(add_counts, None);
+    assert_eq!(counts.iter().collect::<Vec<_>>(), vec![(1, 65535), (2, 3)]);
+}
+
+/// A simple xorshift generator, so the test doesn't need a dependency
+fn pseudo_random_keys(len: usize, key_mask: u64) -> Vec<u64> {
+    let mut state = 0x2545f4914f6cdd1du64;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state & key_mask
+        })
+        .collect()
+}
+
+#[test]
+fn radix_sort_matches_std_sort() {
+    ::env_logger::init().ok();
+
+    for &key_mask in [0xfff, 0xffff_0000_ffff, !0].iter() {
+        let mut keys = pseudo_random_keys(100_000, key_mask);
+        let mut values = vec![1u16; keys.len()];
+        let mut expected = keys.clone();
+        expected.sort();
+        expected.dedup();
+
+        let mut pool = ::jobsteal::make_pool(4).unwrap();
+        sort(&mut keys,
+             &mut values,
+             |_, acc, other| *acc += other,
+             Some(&pool.spawner()));
+        assert_eq!(keys, expected);
+        assert_eq!(values.iter().map(|&n| n as usize).sum::<usize>(), 100_000);
+    }
+}
+
+#[test]
+fn radix_sort_keeps_values_with_keys() {
+    let mut keys = pseudo_random_keys(10_000, !0);
+    let mut values = keys.clone();
+    let distinct = radix_sort(keys.as_mut_slice(), values.as_mut_slice(), &|_, _, _| {}, None);
+    keys.truncate(distinct);
+    values.truncate(distinct);
+    assert!(keys.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(keys, values);
+}
+
+#[test]
+fn radix_sort_sorted_and_reversed_input() {
+    let mut pool = ::jobsteal::make_pool(4).unwrap();
+    let sorted = (0..200_000u64).collect::<Vec<_>>();
+    let reversed = (0..200_000u64).rev().map(|k| k << 40).collect();
+    for keys in [sorted, reversed].iter() {
+        let mut keys = keys.clone();
+        let mut values = keys.clone();
+        let distinct = radix_sort(keys.as_mut_slice(),
+                                  values.as_mut_slice(),
+                                  &|_, _, _| {},
+                                  Some(&pool.spawner()));
+        assert_eq!(distinct, keys.len());
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(keys, values);
+    }
+}
@@ -0,0 +1,42 @@
+use std::time::Duration;
+
+use stats::{Format, Stats};
+
+fn stats() -> Stats {
+    Stats {
+        records: vec![("reads.fq".to_string(), 3), ("a \"b\"\\c\t.fa".to_string(), 1)],
+        bases: 300,
+        invalid: 2,
+        kmers: Some(120),
+        distinct: Some(80),
+        singletons: None,
+        max_count: None,
+        read_time: Duration::from_millis(1500),
+        ..Stats::default()
+    }
+}
+
+fn write(stats: &Stats, format: Format) -> String {
+    let mut out = Vec::new();
+    stats.write(&mut out, format).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn tsv_stats() {
+    assert_eq!(write(&stats(), Format::Tsv),
+               "records:reads.fq\t3\nrecords:a \"b\"\\c\t.fa\t1\nbases\t300\n\
+                invalid_characters\t2\nkmers\t120\ndistinct_kmers\t80\nsingletons\tNA\n\
+                max_count\tNA\nread_seconds\t1.500\ncount_seconds\t0.000\n\
+                consolidate_seconds\t0.000\noutput_seconds\t0.000\n");
+}
+
+#[test]
+fn json_stats_escape_names() {
+    assert_eq!(write(&stats(), Format::Json),
+               "{\"records\":{\"reads.fq\":3,\"a \\\"b\\\"\\\\c\\u0009.fa\":1},\"bases\":300,\
+                \"invalid_characters\":2,\"kmers\":120,\"distinct_kmers\":80,\
+                \"singletons\":null,\"max_count\":null,\"read_seconds\":1.500,\
+                \"count_seconds\":0.000,\"consolidate_seconds\":0.000,\
+                \"output_seconds\":0.000}\n");
+}
@@ -0,0 +1,27 @@
+use translate::{six_frames, GeneticCode};
+
+#[test]
+fn splits_at_stops() {
+    // Forward frame 0 reads M A * W, and TGA is only a stop in the standard code
+    let seq = b"ATGGCCTAATGA";
+    let standard = six_frames(seq, GeneticCode::Standard);
+    assert_eq!(standard[0], b"MA".to_vec());
+    assert!(standard.iter().all(|p| !p.contains(&b'*')));
+    let mitochondrial = six_frames(seq, GeneticCode::Mitochondrial);
+    assert_eq!(mitochondrial[0], b"MA".to_vec());
+    assert_eq!(mitochondrial[1], b"W".to_vec());
+}
+
+#[test]
+fn reverse_frames() {
+    // The reverse complement of CATTTT is AAAATG, read as K M in frame 0
+    let peptides = six_frames(b"CATTTT", GeneticCode::Bacterial);
+    assert!(peptides.contains(&b"KM".to_vec()));
+}
+
+#[test]
+fn invalid_bases_break() {
+    let peptides = six_frames(b"ATGNNNGCC", GeneticCode::Standard);
+    assert_eq!(peptides[0], b"M".to_vec());
+    assert_eq!(peptides[1], b"A".to_vec());
+}
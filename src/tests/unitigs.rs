@@ -0,0 +1,79 @@
+use alphabet::Dna;
+use get_kmers::{SliceKmers, Masking};
+use kmer_counts::KmerCounts;
+use kmer_length::KmerLength;
+use unitigs::{canonical_leaf, Graph};
+
+fn graph(seqs: &[&[u8]], kmer_len: KmerLength<Dna>) -> Graph<Dna> {
+    let mut counts = KmerCounts::new();
+    let mut kmers = SliceKmers::new(kmer_len, Masking::All);
+    for seq in seqs {
+        kmers.for_each(seq, |kmer| counts.push(kmer, 1));
+    }
+    Graph::new(canonical_leaf(counts, kmer_len, 1), kmer_len).unwrap()
+}
+
+fn reverse_complement(seq: &[u8]) -> Vec<u8> {
+    seq.iter()
+        .rev()
+        .map(|&c| match c {
+            b'A' => b'T',
+            b'C' => b'G',
+            b'G' => b'C',
+            _ => b'A',
+        })
+        .collect()
+}
+
+#[test]
+fn linear_path() {
+    let seq = b"ATACGCCTTTACTTGCT";
+    let graph = graph(&[seq, &reverse_complement(seq)], KmerLength::new(5));
+    let unitigs = graph.unitigs();
+    assert_eq!(unitigs.len(), 1);
+    let found = graph.sequence(&unitigs[0]);
+    assert!(found == seq.to_vec() || found == reverse_complement(seq));
+    assert_eq!(graph.total_count(&unitigs[0]), 2 * (seq.len() as u64 - 4));
+    assert!(graph.edges(&unitigs).is_empty());
+}
+
+#[test]
+fn branch() {
+    // Two paths share their first bases, then split
+    let graph = graph(&[b"GCTAAAGACAAT", b"GCTAAAGATACA"], KmerLength::new(5));
+    let unitigs = graph.unitigs();
+    let mut seqs = unitigs.iter().map(|unitig| graph.sequence(unitig)).collect::<Vec<_>>();
+    for seq in seqs.iter_mut() {
+        let rc = reverse_complement(seq);
+        if rc < *seq {
+            *seq = rc;
+        }
+    }
+    seqs.sort();
+    assert_eq!(seqs,
+               vec![b"AAGACAAT".to_vec(), b"AAGATACA".to_vec(), b"GCTAAAGA".to_vec()]);
+    let edges = graph.edges(&unitigs);
+    assert_eq!(edges.len(), 2);
+    // Both edges leave the same end of the shared unitig
+    let shared = unitigs.iter().position(|unitig| unitig.kmers.len() == 4).unwrap();
+    let ends = edges.iter()
+        .map(|edge| if edge.from == shared {
+            (edge.from, edge.from_forward)
+        } else {
+            (edge.to, !edge.to_forward)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(ends[0].0, shared);
+    assert_eq!(ends[0], ends[1]);
+}
+
+#[test]
+fn full_length_kmers() {
+    let seq = b"ATACGCCTTTACTTGCTGATCCAGTTAGCATGCA";
+    assert!(graph(&[], KmerLength::new(32)).unitigs().is_empty());
+    let graph = graph(&[seq], KmerLength::new(32));
+    let unitigs = graph.unitigs();
+    assert_eq!(unitigs.len(), 1);
+    let found = graph.sequence(&unitigs[0]);
+    assert!(found == seq.to_vec() || found == reverse_complement(seq));
+}
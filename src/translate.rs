@@ -0,0 +1,73 @@
+use alphabet::Alphabet;
+use alphabet::Dna;
+
+/// The genetic code tables from NCBI, indexed by codon with bases ordered TCAG
+/// (so TTT is 0 and GGG is 63). Stop codons are `*`.
+const STANDARD_TABLE: &[u8; 64] = b"FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG";
+const MITOCHONDRIAL_TABLE: &[u8; 64] = b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSS**VVVVAAAADDEEGGGG";
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum GeneticCode {
+    /// NCBI table 1
+    Standard,
+    /// NCBI table 11, which only differs from the standard code in start codons
+    Bacterial,
+    /// NCBI table 2, the vertebrate mitochondrial code
+    Mitochondrial,
+}
+
+impl GeneticCode {
+    fn table(self) -> &'static [u8; 64] {
+        match self {
+            GeneticCode::Standard | GeneticCode::Bacterial => STANDARD_TABLE,
+            GeneticCode::Mitochondrial => MITOCHONDRIAL_TABLE,
+        }
+    }
+}
+
+/// Converts a 2-bit nucleotide to its position in TCAG order
+#[inline]
+fn tcag_index(n: u8) -> usize {
+    [2, 1, 3, 0][n as usize]
+}
+
+/// Translates a single reading frame, splitting it into peptides at stop
+/// codons and at codons containing invalid bases.
+fn translate_frame(bases: &[Option<u8>], table: &[u8; 64], peptides: &mut Vec<Vec<u8>>) {
+    let mut peptide = Vec::with_capacity(bases.len() / 3);
+    for codon in bases.chunks(3).filter(|c| c.len() == 3) {
+        let amino_acid = match (codon[0], codon[1], codon[2]) {
+            (Some(a), Some(b), Some(c)) => {
+                table[tcag_index(a) * 16 + tcag_index(b) * 4 + tcag_index(c)]
+            }
+            _ => b'*',
+        };
+        if amino_acid == b'*' {
+            if !peptide.is_empty() {
+                peptides.push(::std::mem::take(&mut peptide));
+            }
+        } else {
+            peptide.push(amino_acid);
+        }
+    }
+    if !peptide.is_empty() {
+        peptides.push(peptide);
+    }
+}
+
+/// Translates a DNA sequence in all six reading frames.
+/// Returns the peptides between stop codons as amino acid text bytes.
+pub fn six_frames(seq: &[u8], code: GeneticCode) -> Vec<Vec<u8>> {
+    let table = code.table();
+    let forward = seq.iter().map(|&c| Dna::encode(c)).collect::<Vec<_>>();
+    let reverse = forward.iter().rev().map(|n| n.map(|n| 3 - n)).collect::<Vec<_>>();
+    let mut peptides = Vec::new();
+    for strand in [forward, reverse].iter() {
+        for frame in 0..3 {
+            if frame < strand.len() {
+                translate_frame(&strand[frame..], table, &mut peptides);
+            }
+        }
+    }
+    peptides
+}
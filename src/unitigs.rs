@@ -0,0 +1,293 @@
+//! Compacted de Bruijn graphs of counted nucleotide k-mers. Each k-mer and
+//! its reverse complement are one node, stored as the canonical k-mer, and
+//! nodes overlap by k - 1 bases. Unitigs are the maximal paths without
+//! branches.
+
+use std::cmp;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::io::BufWriter;
+
+use errors::*;
+use alphabet::{Alphabet, Dna};
+use kmer_length::KmerLength;
+use kmer_counts;
+use kmer_counts::KmerCounts;
+use kmer_tree::Leaf;
+use database;
+use output_counts;
+
+/// The options of the `unitigs` subcommand
+pub struct Options {
+    pub database: String,
+    /// K-mers seen fewer times are left out of the graph
+    pub min_count: u16,
+    /// Also write the graph, with its edges, as GFA1 to this file
+    pub gfa: Option<String>,
+}
+
+/// Turns counts into a sorted leaf of canonical k-mers of at least `min_count`
+pub fn canonical_leaf<A: Alphabet>(mut counts: KmerCounts,
+                                   kmer_len: KmerLength<A>,
+                                   min_count: u16)
+                                   -> Leaf {
+    for kmer in counts.kmers.iter_mut() {
+        *kmer = kmer_len.canonical(*kmer);
+    }
+    counts.sort(kmer_counts::add_counts, None);
+    let counts = counts.iter().filter(|&(_, count)| count >= min_count).collect();
+    Leaf {
+        counts: counts,
+        sorted: true,
+    }
+}
+
+/// A maximal non-branching path, as its k-mers in the order of the path
+pub struct Unitig {
+    pub kmers: Vec<u64>,
+}
+
+/// An overlap of k - 1 bases from the end of one unitig to the start of
+/// another, in the given orientations (true is forward)
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub struct Edge {
+    pub from: usize,
+    pub from_forward: bool,
+    pub to: usize,
+    pub to_forward: bool,
+}
+
+impl Edge {
+    /// The same overlap seen from the other unitig
+    fn reversed(&self) -> Edge {
+        Edge {
+            from: self.to,
+            from_forward: !self.to_forward,
+            to: self.from,
+            to_forward: !self.from_forward,
+        }
+    }
+}
+
+/// The nodes are bucketed by their leading bits, with about this many nodes
+/// per bucket
+const BUCKET_NODES: usize = 4;
+
+pub struct Graph<A> {
+    kmer_len: KmerLength<A>,
+    /// Sorted canonical k-mers, searched for the overlaps
+    nodes: KmerCounts,
+    /// How far the k-mers are shifted to get their bucket
+    bucket_shift: u32,
+    /// Where the nodes of each bucket start, and the end of the last, so a
+    /// search only goes over the nodes with the same leading bits
+    buckets: Vec<usize>,
+}
+
+impl<A: Alphabet> Graph<A> {
+    /// `leaf` must be sorted and made of canonical k-mers
+    pub fn new(leaf: Leaf, kmer_len: KmerLength<A>) -> Result<Graph<A>> {
+        if !A::COMPLEMENTED {
+            bail!("De Bruijn graphs can only be built from nucleotide k-mers");
+        }
+        if !leaf.sorted {
+            bail!("De Bruijn graphs need sorted k-mers");
+        }
+        let bits = A::BITS as u32 * kmer_len.length() as u32;
+        let bucket_bits = cmp::max(64 - (leaf.counts.len() / BUCKET_NODES).leading_zeros(), 1);
+        let bucket_shift = bits.saturating_sub(bucket_bits);
+        let mut buckets = Vec::with_capacity((1 << (bits - bucket_shift)) + 1);
+        let mut start = 0;
+        for bucket in 0..(1u64 << (bits - bucket_shift)) {
+            buckets.push(start);
+            start += leaf.counts.kmers[start..]
+                .iter()
+                .take_while(|&&kmer| kmer >> bucket_shift == bucket)
+                .count();
+        }
+        buckets.push(start);
+        Ok(Graph {
+            kmer_len: kmer_len,
+            nodes: leaf.counts,
+            bucket_shift: bucket_shift,
+            buckets: buckets,
+        })
+    }
+
+    /// The index of the node of an oriented k-mer
+    #[inline]
+    fn index(&self, kmer: u64) -> Option<usize> {
+        let kmer = self.kmer_len.canonical(kmer);
+        let bucket = (kmer >> self.bucket_shift) as usize;
+        let (start, end) = (self.buckets[bucket], self.buckets[bucket + 1]);
+        self.nodes.kmers[start..end].binary_search(&kmer).ok().map(|i| start + i)
+    }
+
+    /// The oriented k-mers following one
+    fn successors(&self, kmer: u64) -> Vec<u64> {
+        let shifted = (kmer << 2) & self.kmer_len.bitmask();
+        (0..4).map(|n| shifted | n).filter(|&next| self.index(next).is_some()).collect()
+    }
+
+    /// The number of oriented k-mers preceding one
+    fn predecessor_count(&self, kmer: u64) -> usize {
+        self.successors(self.kmer_len.reverse_complement(kmer)).len()
+    }
+
+    /// Follows the path from `start` while it doesn't branch, marking the
+    /// k-mers it takes as visited, and returns them
+    fn extend(&self, start: u64, visited: &mut [bool]) -> Vec<u64> {
+        let mut path = Vec::new();
+        let mut kmer = start;
+        loop {
+            let next = self.successors(kmer);
+            if next.len() != 1 || self.predecessor_count(next[0]) != 1 {
+                break;
+            }
+            let index = self.index(next[0]).unwrap();
+            // Also stops cycles, and hairpins into the reverse complement
+            if visited[index] {
+                break;
+            }
+            visited[index] = true;
+            path.push(next[0]);
+            kmer = next[0];
+        }
+        path
+    }
+
+    /// Every unitig, each k-mer is in exactly one of them
+    pub fn unitigs(&self) -> Vec<Unitig> {
+        let mut visited = vec![false; self.nodes.len()];
+        let mut unitigs = Vec::new();
+        for (index, &seed) in self.nodes.kmers.iter().enumerate() {
+            if visited[index] {
+                continue;
+            }
+            visited[index] = true;
+            let forward = self.extend(seed, &mut visited);
+            let backward = self.extend(self.kmer_len.reverse_complement(seed), &mut visited);
+            let mut kmers = backward.into_iter()
+                .rev()
+                .map(|kmer| self.kmer_len.reverse_complement(kmer))
+                .collect::<Vec<_>>();
+            kmers.push(seed);
+            kmers.extend(forward);
+            unitigs.push(Unitig { kmers: kmers });
+        }
+        unitigs
+    }
+
+    /// Every overlap between unitigs, each given once
+    pub fn edges(&self, unitigs: &[Unitig]) -> Vec<Edge> {
+        // The oriented k-mers which unitigs start with, in either orientation,
+        // sorted to be searched like the nodes
+        let mut starts = Vec::with_capacity(2 * unitigs.len());
+        for (i, unitig) in unitigs.iter().enumerate() {
+            starts.push((unitig.kmers[0], (i, true)));
+            starts.push((self.kmer_len.reverse_complement(*unitig.kmers.last().unwrap()),
+                         (i, false)));
+        }
+        starts.sort_unstable();
+        let mut edges = Vec::new();
+        for (i, unitig) in unitigs.iter().enumerate() {
+            let ends = [(*unitig.kmers.last().unwrap(), true),
+                        (self.kmer_len.reverse_complement(unitig.kmers[0]), false)];
+            for &(end, forward) in ends.iter() {
+                for next in self.successors(end) {
+                    let start = starts.binary_search_by_key(&next, |&(kmer, _)| kmer);
+                    if let Ok(found) = start {
+                        let (to, to_forward) = starts[found].1;
+                        let edge = Edge {
+                            from: i,
+                            from_forward: forward,
+                            to: to,
+                            to_forward: to_forward,
+                        };
+                        if edge <= edge.reversed() {
+                            edges.push(edge);
+                        }
+                    }
+                }
+            }
+        }
+        edges.sort();
+        edges.dedup();
+        edges
+    }
+
+    pub fn sequence(&self, unitig: &Unitig) -> Vec<u8> {
+        let mut seq = output_counts::kmer_text(unitig.kmers[0], self.kmer_len);
+        seq.extend(unitig.kmers[1..].iter().map(|&kmer| A::decode((kmer & 3) as u8)));
+        seq
+    }
+
+    /// The total count of a unitig's k-mers
+    pub fn total_count(&self, unitig: &Unitig) -> u64 {
+        unitig.kmers
+            .iter()
+            .map(|&kmer| self.nodes.counts[self.index(kmer).unwrap()] as u64)
+            .sum()
+    }
+}
+
+/// Writes unitigs as FASTA, with their length, total k-mer count and mean
+/// k-mer coverage in the header in the style of BCALM
+fn write_fasta<A: Alphabet, W: Write>(mut stream: W, graph: &Graph<A>, unitigs: &[Unitig]) -> io::Result<()> {
+    for (i, unitig) in unitigs.iter().enumerate() {
+        let seq = graph.sequence(unitig);
+        let total = graph.total_count(unitig);
+        try!(writeln!(stream,
+                      ">{} LN:i:{} KC:i:{} km:f:{:.1}",
+                      i,
+                      seq.len(),
+                      total,
+                      total as f64 / unitig.kmers.len() as f64));
+        try!(stream.write_all(&seq));
+        try!(writeln!(stream));
+    }
+    stream.flush()
+}
+
+fn orientation(forward: bool) -> char {
+    if forward { '+' } else { '-' }
+}
+
+fn write_gfa<A: Alphabet, W: Write>(mut stream: W, graph: &Graph<A>, unitigs: &[Unitig]) -> io::Result<()> {
+    try!(writeln!(stream, "H\tVN:Z:1.0"));
+    for (i, unitig) in unitigs.iter().enumerate() {
+        try!(write!(stream, "S\t{}\t", i));
+        try!(stream.write_all(&graph.sequence(unitig)));
+        try!(writeln!(stream, "\tKC:i:{}", graph.total_count(unitig)));
+    }
+    for edge in graph.edges(unitigs) {
+        try!(writeln!(stream,
+                      "L\t{}\t{}\t{}\t{}\t{}M",
+                      edge.from,
+                      orientation(edge.from_forward),
+                      edge.to,
+                      orientation(edge.to_forward),
+                      graph.kmer_len.length() - 1));
+    }
+    stream.flush()
+}
+
+pub fn run(opts: Options) -> Result<()> {
+    let db = try!(database::open::<Dna>(&opts.database));
+    let leaf = canonical_leaf(db.counts, db.kmer_len, opts.min_count);
+    info!("Building a de Bruijn graph of {} canonical k-mers", leaf.counts.len());
+    let graph = try!(Graph::new(leaf, db.kmer_len));
+    let unitigs = graph.unitigs();
+    info!("Found {} unitigs", unitigs.len());
+    let stdout = io::stdout();
+    try!(write_fasta(BufWriter::new(stdout.lock()), &graph, &unitigs)
+        .chain_err(|| "Failed to write unitigs"));
+    if let Some(ref path) = opts.gfa {
+        let file = try!(File::create(path).chain_err(|| "Failed to create GFA file"));
+        try!(write_gfa(BufWriter::new(file), &graph, &unitigs)
+            .chain_err(|| "Failed to write GFA file"));
+        info!("Saved the graph to {}", path);
+    }
+    Ok(())
+}